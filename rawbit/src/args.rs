@@ -1,4 +1,7 @@
 use std::{
+    env,
+    ffi::OsString,
+    fmt::Display,
     fs::read_dir,
     path::{Path, PathBuf},
     thread::available_parallelism,
@@ -10,14 +13,24 @@ use clap::{
         styling::{AnsiColor, Color, Style},
         IntoResettable, Styles,
     },
-    command, value_parser, ArgAction, Args, Parser,
+    command,
+    error::ErrorKind,
+    value_parser, ArgAction, Args, CommandFactory as _, Parser, Subcommand,
 };
 use rayon::iter::{IntoParallelIterator as _, ParallelBridge as _, ParallelIterator as _};
 use smlog::{debug, warn};
 
-use rawler::decoders::supported_extensions;
+use rawler::{decoders::supported_extensions, dng::convert::ConvertParams};
 
-use crate::common::{map_err, AppError, RawbitResult};
+use crate::{
+    common::{map_err, AppError, RawbitResult},
+    config::Config,
+    events::OutputFormat,
+    job::{Compression, Crop, Mode, Options},
+    plan::{Collision, ImageSelection},
+    sanitize::Profile,
+    verify::Verify,
+};
 
 macro_rules! style {
     ($style:expr) => {
@@ -35,17 +48,290 @@ const fn cli_style() -> Styles {
         .placeholder(style!(AnsiColor::Cyan))
 }
 
-#[allow(clippy::struct_excessive_bools)]
 #[derive(Debug, Parser)]
 #[command(
     version,
     about = "A camera RAW image preprocessor and importer",
     long_about = None,
-    trailing_var_arg = true,
+    args_conflicts_with_subcommands = true,
+    subcommand_negates_reqs = true,
     styles = cli_style(),
     next_line_help = true,
     color = clap::ColorChoice::Always
 )]
+pub struct Cli {
+    #[command(subcommand)]
+    pub command: Option<Command>,
+
+    #[command(flatten)]
+    pub convert: ImportConfig,
+}
+
+#[derive(Debug, Subcommand)]
+pub enum Command {
+    /// Convert RAW files to DNG; this is what rawbit does when no command is given
+    Convert(Box<ImportConfig>),
+
+    /// Print what every {...} expansion renders to for RAW files, along with their EXIF metadata
+    Inspect(InspectConfig),
+
+    /// Rename RAW files or DNGs in place, or move them into a new directory tree, without converting
+    Rename(RenameConfig),
+
+    /// Revert a run of convert or rename from its journal, leaving files that were modified since
+    Undo(UndoConfig),
+
+    /// Re-check the outputs listed in a manifest written with --manifest
+    #[command(alias = "verify-manifest")]
+    Verify(VerifyConfig),
+}
+
+impl Cli {
+    /// Parses the command line and the configuration files; exits on errors
+    pub fn load() -> Command {
+        Self::from_config(env::args_os().collect(), Config::load)
+            .unwrap_or_else(|err| err.exit())
+            .into_command()
+    }
+
+    /// `load` is only called for `convert`, so that a broken configuration file doesn't get in the
+    /// way of the other commands.
+    pub fn from_config<E: Display>(
+        argv: Vec<OsString>,
+        load: impl FnOnce() -> Result<Config, E>,
+    ) -> Result<Self, clap::Error> {
+        let mut cmd = Self::command();
+        cmd.build();
+
+        // only tells which options were given, a missing --out-dir may still come from `config`
+        let given = cmd
+            .clone()
+            .ignore_errors(true)
+            .try_get_matches_from(&argv)?;
+
+        // the configured options go right after the command's name, before any files
+        let (target, matches, at) = match given.subcommand() {
+            None => (&cmd, &given, 1),
+            Some(("convert", matches)) => (cmd.find_subcommand("convert").unwrap(), matches, 2),
+            Some(_) => return Self::try_parse_from(argv),
+        };
+
+        let config = load().map_err(|err| cmd.clone().error(ErrorKind::Io, err))?;
+        let profile = matches.get_one::<String>("profile").map(String::as_str);
+        let configured = config
+            .args(target, matches, profile)
+            .map_err(|err| cmd.clone().error(ErrorKind::InvalidValue, err))?;
+
+        let mut argv = argv;
+        let at = at.min(argv.len());
+        argv.splice(at..at, configured);
+
+        Self::try_parse_from(argv)
+    }
+
+    pub fn into_command(self) -> Command {
+        self.command
+            .unwrap_or_else(|| Command::Convert(Box::new(self.convert)))
+    }
+}
+
+impl Command {
+    pub const fn log_config(&self) -> &LogConfig {
+        match self {
+            Self::Convert(args) => &args.log_config,
+            Self::Inspect(args) => &args.log_config,
+            Self::Rename(args) => &args.log_config,
+            Self::Undo(args) => &args.log_config,
+            Self::Verify(args) => &args.log_config,
+        }
+    }
+
+    pub fn output_format(&self) -> OutputFormat {
+        match self {
+            Self::Convert(args) => args.output_format,
+            Self::Inspect(args) => args.output_format,
+            Self::Rename(args) => args.output_format,
+            Self::Undo(_) | Self::Verify(_) => OutputFormat::default(),
+        }
+    }
+
+    pub fn n_threads(&self) -> usize {
+        match self {
+            Self::Convert(args) => args.n_threads(),
+            Self::Inspect(_) | Self::Rename(_) | Self::Undo(_) | Self::Verify(_) => {
+                default_threads()
+            }
+        }
+    }
+
+    pub fn validate(&self) -> Result<(), clap::Error> {
+        match self {
+            Self::Convert(args) => args.validate(),
+            Self::Rename(args) => args.validate(),
+            Self::Inspect(_) | Self::Undo(_) | Self::Verify(_) => Ok(()),
+        }
+    }
+}
+
+fn default_threads() -> usize {
+    available_parallelism().unwrap().get()
+}
+
+#[derive(Debug, Args)]
+#[command(trailing_var_arg = true)]
+pub struct InspectConfig {
+    #[command(flatten)]
+    pub source: RawSource,
+
+    #[arg(
+        short,
+        long,
+        action = ArgAction::Set,
+        default_value_t = false,
+        default_missing_value = "true",
+        require_equals = true,
+        value_name = "BOOL",
+        num_args = 0..=1,
+        help = "read images from subdirectories as well"
+    )]
+    pub recurse: bool,
+
+    #[arg(
+        long,
+        value_enum,
+        value_name = "FORMAT",
+        default_value_t = OutputFormat::Text,
+        help = "print a table per image, or one JSON object per image"
+    )]
+    pub output_format: OutputFormat,
+
+    #[command(flatten)]
+    pub log_config: LogConfig,
+}
+
+#[derive(Debug, Args)]
+#[command(trailing_var_arg = true)]
+pub struct RenameConfig {
+    #[command(flatten)]
+    pub source: RawSource,
+
+    #[arg(
+        short = 'o',
+        long = "out-dir",
+        value_name = "DIR",
+        help = "move renamed files into this directory, keeping their layout below --in-dir; by default they're renamed where they are"
+    )]
+    pub output_dir: Option<PathBuf>,
+
+    #[arg(
+        short = 'F',
+        long = "format",
+        value_name = "FORMAT",
+        help = "filename format of renamed files; the extension is kept"
+    )]
+    pub fmt_str: String,
+
+    #[arg(
+        long,
+        value_enum,
+        value_name = "PROFILE",
+        default_value_t = Profile::native(),
+        help = "make new paths valid on this kind of filesystem"
+    )]
+    pub sanitize: Profile,
+
+    #[arg(
+        long,
+        value_enum,
+        value_name = "STRATEGY",
+        default_value_t = Collision::default(),
+        help = "what to do when a new path is used more than once, or already exists; existing files are never replaced"
+    )]
+    pub on_collision: Collision,
+
+    #[arg(
+        short,
+        long,
+        action = ArgAction::Set,
+        default_value_t = false,
+        default_missing_value = "true",
+        require_equals = true,
+        value_name = "BOOL",
+        num_args = 0..=1,
+        help = "rename files in subdirectories as well"
+    )]
+    pub recurse: bool,
+
+    #[arg(
+        long,
+        action = ArgAction::Set,
+        default_value_t = false,
+        default_missing_value = "true",
+        require_equals = true,
+        value_name = "BOOL",
+        num_args = 0..=1,
+        help = "print what every file would be renamed to, without renaming anything"
+    )]
+    pub dry_run: bool,
+
+    #[arg(
+        long,
+        value_enum,
+        value_name = "FORMAT",
+        default_value_t = OutputFormat::Text,
+        help = "how progress is reported on stdout"
+    )]
+    pub output_format: OutputFormat,
+
+    #[command(flatten)]
+    pub log_config: LogConfig,
+}
+
+impl RenameConfig {
+    pub fn validate(&self) -> Result<(), clap::Error> {
+        if self.on_collision == Collision::Overwrite {
+            return Err(Cli::command().error(
+                ErrorKind::InvalidValue,
+                "rename never replaces existing files, --on-collision=overwrite can't be used",
+            ));
+        }
+
+        Ok(())
+    }
+
+    pub fn journal_dir(&self) -> PathBuf {
+        self.output_dir
+            .as_ref()
+            .or(self.source.input_dir.as_ref())
+            .cloned()
+            .unwrap_or_else(|| ".".into())
+    }
+}
+
+#[derive(Debug, Args)]
+pub struct UndoConfig {
+    #[arg(
+        value_name = "FILE",
+        help = "the .journal written by convert or rename"
+    )]
+    pub journal: PathBuf,
+
+    #[command(flatten)]
+    pub log_config: LogConfig,
+}
+
+#[derive(Debug, Args)]
+pub struct VerifyConfig {
+    #[arg(value_name = "FILE", help = "the .sha256 manifest to check")]
+    pub manifest: PathBuf,
+
+    #[command(flatten)]
+    pub log_config: LogConfig,
+}
+
+#[allow(clippy::struct_excessive_bools)]
+#[derive(Debug, Args)]
+#[command(trailing_var_arg = true)]
 pub struct ImportConfig {
     #[command(flatten)]
     pub source: RawSource,
@@ -54,15 +340,18 @@ pub struct ImportConfig {
         short = 'o',
         long = "out-dir",
         value_name = "DIR",
+        required = true,
+        env = "RAWBIT_OUT_DIR",
         help = "directory to write converted DNGs"
     )]
-    pub output_dir: PathBuf,
+    pub output_dir: Option<PathBuf>,
 
     #[arg(
         short = 'F',
         long = "format",
         value_name = "FORMAT",
         value_parser = value_parser!(String).into_resettable(),
+        env = "RAWBIT_FORMAT",
         help = "filename format of converted DNGs; see https://docs.rs/rawbit for info on syntax"
     )]
     pub fmt_str: Option<String>,
@@ -72,6 +361,7 @@ pub struct ImportConfig {
         long,
         value_name = "ARTIST",
         value_parser = value_parser!(String).into_resettable(),
+        env = "RAWBIT_ARTIST",
         help = "value of the \"artist\" field in converted DNGs"
     )]
     pub artist: Option<String>,
@@ -89,6 +379,15 @@ pub struct ImportConfig {
     )]
     pub embed: bool,
 
+    #[arg(
+        long,
+        value_enum,
+        value_name = "PROFILE",
+        default_value_t = Profile::native(),
+        help = "make output paths valid on this kind of filesystem"
+    )]
+    pub sanitize: Profile,
+
     #[arg(
         short,
         long,
@@ -98,10 +397,20 @@ pub struct ImportConfig {
         require_equals = true,
         value_name = "BOOL",
         num_args = 0..=1,
-        help = "overwrite existing files, if they exist"
+        conflicts_with = "on_collision",
+        help = "overwrite existing files, if they exist; same as --on-collision=overwrite"
     )]
     pub force: bool,
 
+    #[arg(
+        long,
+        value_enum,
+        value_name = "STRATEGY",
+        default_value_t = Collision::default(),
+        help = "what to do when an output path is used more than once, or already exists"
+    )]
+    pub on_collision: Collision,
+
     #[arg(
         short,
         long,
@@ -120,9 +429,10 @@ pub struct ImportConfig {
         long,
         action = ArgAction::Set,
         default_value_t = true,
+        default_missing_value = "true",
         value_name = "BOOL",
         num_args = 0..=1,
-        help = "Embed image preview in output DNG"
+        help = "embed a JPEG preview in converted DNGs; disable to shrink output files"
     )]
     pub preview: bool,
 
@@ -131,12 +441,180 @@ pub struct ImportConfig {
         long,
         action = ArgAction::Set,
         default_value_t = true,
+        default_missing_value = "true",
         value_name = "BOOL",
         num_args = 0..=1,
-        help = "Embed image thumbnail in output DNG"
+        help = "embed a thumbnail in converted DNGs; disable to shrink output files"
     )]
     pub thumbnail: bool,
 
+    #[arg(
+        long,
+        action = ArgAction::Set,
+        default_value_t = false,
+        default_missing_value = "true",
+        require_equals = true,
+        value_name = "BOOL",
+        num_args = 0..=1,
+        conflicts_with_all = [
+            "embed", "preview", "thumbnail", "compression", "crop", "apply_scaling", "image_index",
+            "all_images", "artist",
+        ],
+        help = "copy RAW files byte-for-byte, keeping their original extension, instead of converting them"
+    )]
+    pub copy: bool,
+
+    #[arg(
+        long,
+        value_enum,
+        value_name = "MODE",
+        default_value_t = Compression::default(),
+        help = "how raw image data is stored in converted DNGs"
+    )]
+    pub compression: Compression,
+
+    #[arg(
+        long,
+        value_enum,
+        value_name = "MODE",
+        default_value_t = Crop::default(),
+        help = "how much of the sensor area is kept in converted DNGs"
+    )]
+    pub crop: Crop,
+
+    #[arg(
+        long,
+        action = ArgAction::Set,
+        default_value_t = false,
+        default_missing_value = "true",
+        require_equals = true,
+        value_name = "BOOL",
+        num_args = 0..=1,
+        help = "normalize pixel values to the black and white levels before writing; lossless DNGs are rescaled to 16 bits"
+    )]
+    pub apply_scaling: bool,
+
+    #[arg(
+        long = "backup-dir",
+        value_name = "DIR",
+        action = ArgAction::Append,
+        help = "also write every output to this directory, with the same layout; may be given more than once"
+    )]
+    pub backup_dirs: Vec<PathBuf>,
+
+    #[arg(
+        long,
+        action = ArgAction::Set,
+        default_value_t = false,
+        default_missing_value = "true",
+        require_equals = true,
+        value_name = "BOOL",
+        num_args = 0..=1,
+        help = "write a sha256sum-compatible manifest of every source and output to the output and backup directories"
+    )]
+    pub manifest: bool,
+
+    #[arg(
+        long,
+        action = ArgAction::Set,
+        default_value_t = false,
+        default_missing_value = "true",
+        require_equals = true,
+        value_name = "BOOL",
+        num_args = 0..=1,
+        requires = "manifest",
+        help = "also write the manifest as ASC-MHL style XML"
+    )]
+    pub mhl: bool,
+
+    #[arg(
+        long,
+        action = ArgAction::Set,
+        default_value_t = true,
+        default_missing_value = "true",
+        require_equals = true,
+        value_name = "BOOL",
+        num_args = 0..=1,
+        help = "record every written file in a journal in the output directory, so that `rawbit undo` can revert the run"
+    )]
+    pub journal: bool,
+
+    #[arg(
+        long,
+        action = ArgAction::Set,
+        default_value_t = false,
+        default_missing_value = "true",
+        require_equals = true,
+        value_name = "BOOL",
+        num_args = 0..=1,
+        help = "print where every image would be written, without writing anything"
+    )]
+    pub dry_run: bool,
+
+    #[arg(
+        long,
+        action = ArgAction::Set,
+        default_value_t = false,
+        default_missing_value = "true",
+        require_equals = true,
+        value_name = "BOOL",
+        num_args = 0..=1,
+        help = "stop at the first image that fails; images that weren't started yet are left alone"
+    )]
+    pub fail_fast: bool,
+
+    #[arg(
+        long = "move",
+        action = ArgAction::Set,
+        default_value_t = false,
+        default_missing_value = "true",
+        require_equals = true,
+        value_name = "BOOL",
+        num_args = 0..=1,
+        help = "delete each source file once all of its outputs were written and verified; implies --verify=pixels, or --verify=decode with --apply-scaling"
+    )]
+    pub move_sources: bool,
+
+    #[arg(
+        long,
+        value_enum,
+        value_name = "LEVEL",
+        num_args = 0..=1,
+        require_equals = true,
+        default_missing_value = "decode",
+        help = "read back every converted DNG and compare it to the source before moving it into place; failed outputs are deleted"
+    )]
+    pub verify: Option<Verify>,
+
+    #[arg(
+        long,
+        value_name = "DIR",
+        requires = "verify",
+        help = "move DNGs that fail verification to this directory instead of deleting them"
+    )]
+    pub quarantine_dir: Option<PathBuf>,
+
+    #[arg(
+        long,
+        value_name = "N",
+        default_value_t = 0,
+        help = "convert the Nth image of RAW files that contain more than one, starting at 0"
+    )]
+    pub image_index: usize,
+
+    #[arg(
+        long,
+        action = ArgAction::Set,
+        default_value_t = false,
+        default_missing_value = "true",
+        require_equals = true,
+        value_name = "BOOL",
+        num_args = 0..=1,
+        conflicts_with = "image_index",
+        help = "convert every image of RAW files that contain more than one; use {image.index} in the format to tell them apart"
+    )]
+    pub all_images: bool,
+
     #[arg(
         short = 'j',
         long,
@@ -144,18 +622,95 @@ pub struct ImportConfig {
         default_missing_value = "",
         num_args = 0..=1,
         value_name = "N",
+        env = "RAWBIT_THREADS",
         help = "number of threads to use while processing input images, defaults to number of CPUs"
     )]
     pub n_threads: Option<usize>,
 
+    #[arg(
+        long,
+        value_enum,
+        value_name = "FORMAT",
+        default_value_t = OutputFormat::Text,
+        help = "how progress is reported on stdout"
+    )]
+    pub output_format: OutputFormat,
+
+    #[arg(
+        long,
+        value_name = "NAME",
+        env = "RAWBIT_PROFILE",
+        help = "use the options of this profile from the configuration files; options given on the command line take precedence"
+    )]
+    pub profile: Option<String>,
+
     #[command(flatten)]
     pub log_config: LogConfig,
 }
 
 impl ImportConfig {
     pub fn n_threads(&self) -> usize {
-        let default_threads = available_parallelism().unwrap().get();
-        self.n_threads.unwrap_or(default_threads)
+        self.n_threads.unwrap_or_else(default_threads)
+    }
+
+    /// Checks the combinations of arguments that clap can't express
+    pub fn validate(&self) -> Result<(), clap::Error> {
+        if self.verify == Some(Verify::Pixels) && self.apply_scaling {
+            return Err(Cli::command().error(
+                ErrorKind::ArgumentConflict,
+                "--verify=pixels can't be used with --apply-scaling, which changes pixel values",
+            ));
+        }
+
+        Ok(())
+    }
+
+    /// Compares pixels before a source is deleted, unless `--apply-scaling` changes them
+    const fn move_verify(&self) -> Verify {
+        if self.apply_scaling {
+            Verify::Decode
+        } else {
+            Verify::Pixels
+        }
+    }
+
+    /// `--force` is shorthand for `--on-collision=overwrite`
+    pub const fn collision_strategy(&self) -> Collision {
+        if self.force {
+            Collision::Overwrite
+        } else {
+            self.on_collision
+        }
+    }
+
+    pub fn job_options(&self) -> Options {
+        Options {
+            mode: if self.copy { Mode::Copy } else { Mode::Convert },
+            convert: ConvertParams {
+                artist: self.artist.clone(),
+                apply_scaling: self.apply_scaling,
+                crop: self.crop.into(),
+                compression: self.compression.into(),
+                embedded: self.embed,
+                preview: self.preview,
+                thumbnail: self.thumbnail,
+                software: "rawbit".into(),
+                ..Default::default()
+            },
+            verify: self
+                .verify
+                .or_else(|| self.move_sources.then(|| self.move_verify())),
+            quarantine_dir: self.quarantine_dir.clone(),
+            hash: self.manifest || self.journal,
+        }
+    }
+
+    pub const fn image_selection(&self) -> ImageSelection {
+        if self.all_images {
+            ImageSelection::All
+        } else {
+            ImageSelection::Index(self.image_index)
+        }
     }
 }
 
@@ -187,12 +742,12 @@ pub struct RawSource {
         long = "in-dir",
         value_name = "DIR",
         value_parser = value_parser!(PathBuf).into_resettable(),
-        help = "directory containing raw files to convert"
+        help = "directory containing raw files"
     )]
     pub input_dir: Option<PathBuf>,
 
     #[arg(
-        help = "individual files to convert",
+        help = "individual raw files",
         trailing_var_arg = true,
         value_parser = value_parser!(PathBuf).into_resettable()
     )]
@@ -224,24 +779,30 @@ impl RawSource {
         supported_extensions().contains(&ext.as_ref()) || ext.to_lowercase() == "dng"
     }
 
-    fn ingest_files(files: Vec<PathBuf>) -> Vec<IngestItem> {
-        files
-            .into_par_iter()
-            .filter_map(|ref item| {
-                if Self::is_supported_filetype(item) {
-                    debug!("found supported file: \"{}\"", item.display());
+    fn ingest_file(path: PathBuf, prefix: &Path) -> Result<IngestItem, PathBuf> {
+        if Self::is_supported_filetype(&path) {
+            debug!("found supported file: \"{}\"", path.display());
 
-                    Some((item, "").into())
-                } else {
-                    warn!("ignoring \"{}\": unsupported filetype", item.display());
+            Ok((path, prefix).into())
+        } else {
+            warn!("ignoring \"{}\": unsupported filetype", path.display());
 
-                    None
-                }
-            })
+            Err(path)
+        }
+    }
+
+    fn ingest_files(files: Vec<PathBuf>) -> Vec<Result<IngestItem, PathBuf>> {
+        files
+            .into_par_iter()
+            .map(|item| Self::ingest_file(item, Path::new("")))
             .collect::<Vec<_>>()
     }
 
-    fn ingest_dir(input_dir: &Path, prefix: &Path, recurse: bool) -> RawbitResult<Vec<IngestItem>> {
+    fn ingest_dir(
+        input_dir: &Path,
+        prefix: &Path,
+        recurse: bool,
+    ) -> RawbitResult<Vec<Result<IngestItem, PathBuf>>> {
         if !input_dir.is_dir() {
             return Err(AppError::DirNotFound(
                 "source directory doesn't exist".into(),
@@ -265,17 +826,7 @@ impl RawSource {
                 }
 
                 Ok(ref item) if item.path().is_file() => {
-                    let path = item.path();
-
-                    if Self::is_supported_filetype(&path) {
-                        debug!("found supported file: \"{}\"", path.display());
-
-                        Some(Ok(vec![(path, prefix.to_path_buf()).into()]))
-                    } else {
-                        warn!("ignoring \"{}\": unsupported filetype", path.display());
-
-                        None
-                    }
+                    Some(Ok(vec![Self::ingest_file(item.path(), prefix)]))
                 }
 
                 _ => None,
@@ -288,19 +839,27 @@ impl RawSource {
         Ok(files)
     }
 
-    pub fn ingest(self, recurse: bool) -> RawbitResult<Vec<IngestItem>> {
+    /// Also returns the files that were skipped because they aren't supported
+    pub fn ingest(self, recurse: bool) -> RawbitResult<(Vec<IngestItem>, Vec<PathBuf>)> {
         assert!(
             self.files.is_some() || self.input_dir.is_some(),
             "expected input dir or a list of individual files, got neither"
         );
 
-        if let Some(ref dir) = self.input_dir {
-            Self::ingest_dir(dir, &PathBuf::new(), recurse)
+        let found = if let Some(ref dir) = self.input_dir {
+            Self::ingest_dir(dir, &PathBuf::new(), recurse)?
         } else if let Some(files) = self.files {
-            Ok(Self::ingest_files(files))
+            Self::ingest_files(files)
         } else {
             unreachable!()
-        }
+        };
+
+        let (ingest, unsupported): (Vec<_>, Vec<_>) = found.into_iter().partition(Result::is_ok);
+
+        Ok((
+            ingest.into_iter().filter_map(Result::ok).collect(),
+            unsupported.into_iter().filter_map(Result::err).collect(),
+        ))
     }
 }
 
@@ -354,7 +913,7 @@ mod path_tests {
             files: None,
         };
 
-        let ingest = args.ingest(false).unwrap();
+        let (ingest, _) = args.ingest(false).unwrap();
         assert_eq!(ingest.len(), 10);
 
         for IngestItem {
@@ -379,7 +938,7 @@ mod path_tests {
             files: None,
         };
 
-        let ingest = args.ingest(true).unwrap();
+        let (ingest, _) = args.ingest(true).unwrap();
         assert_eq!(ingest.len(), 20);
 
         for IngestItem {
@@ -406,7 +965,7 @@ mod path_tests {
             files: None,
         };
 
-        let ingest = args.ingest(false).unwrap();
+        let (ingest, _) = args.ingest(false).unwrap();
         assert_eq!(ingest.len(), 10);
 
         for IngestItem {
@@ -422,4 +981,307 @@ mod path_tests {
 
         Ok(())
     }
+
+    #[test]
+    fn returns_unsupported_files() -> Result<()> {
+        let (input_dir, _) = setup_flat_dir(None)?;
+        let notes = input_dir.path().join("notes.txt");
+        File::create(&notes)?;
+
+        let args = RawSource {
+            input_dir: Some(input_dir.path().to_path_buf()),
+            files: None,
+        };
+
+        let (ingest, unsupported) = args.ingest(false).unwrap();
+        assert_eq!(ingest.len(), 10);
+        assert_eq!(unsupported, [notes]);
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod cli_tests {
+    use std::{iter, path::Path};
+
+    use clap::Parser as _;
+
+    use super::{Cli, Command, ImportConfig};
+    use crate::{
+        config::Config,
+        job::{Compression, Crop},
+        plan::{Collision, ImageSelection},
+        verify::Verify,
+    };
+
+    fn convert_args(cli: Cli) -> ImportConfig {
+        let Command::Convert(args) = cli.into_command() else {
+            panic!("expected convert");
+        };
+
+        *args
+    }
+
+    fn parse(args: &[&str]) -> ImportConfig {
+        convert_args(Cli::try_parse_from(["rawbit", "-o", "out"].iter().chain(args)).unwrap())
+    }
+
+    #[test]
+    fn previews_are_embedded_by_default() {
+        let args = parse(&["a.RAF"]);
+        assert!(args.preview);
+        assert!(args.thumbnail);
+
+        let args = parse(&["--preview", "-t", "-i", "raw"]);
+        assert!(args.preview);
+        assert!(args.thumbnail);
+    }
+
+    #[test]
+    fn previews_can_be_disabled() {
+        let args = parse(&["--preview=false", "-t=false", "a.RAF"]);
+        assert!(!args.preview);
+        assert!(!args.thumbnail);
+        assert_eq!(args.source.files.unwrap().len(), 1);
+
+        let args = parse(&["-p", "false", "--thumbnail", "false", "a.RAF"]);
+        assert!(!args.preview);
+        assert!(!args.thumbnail);
+        assert_eq!(args.source.files.unwrap().len(), 1);
+    }
+
+    #[test]
+    fn parses_conversion_modes() {
+        let args = parse(&["a.RAF"]);
+        assert_eq!(args.compression, Compression::Lossless);
+        assert_eq!(args.crop, Crop::Best);
+        assert!(!args.apply_scaling);
+
+        let args = parse(&[
+            "--compression",
+            "uncompressed",
+            "--crop=activearea",
+            "--apply-scaling",
+            "a.RAF",
+        ]);
+        assert_eq!(args.compression, Compression::Uncompressed);
+        assert_eq!(args.crop, Crop::ActiveArea);
+        assert!(args.apply_scaling);
+
+        assert!(Cli::try_parse_from(["rawbit", "-o", "out", "--crop=full", "a"]).is_err());
+    }
+
+    #[test]
+    fn selects_images() {
+        assert_eq!(
+            parse(&["a.RAF"]).image_selection(),
+            ImageSelection::Index(0)
+        );
+        assert_eq!(
+            parse(&["--image-index", "3", "a.RAF"]).image_selection(),
+            ImageSelection::Index(3)
+        );
+        assert_eq!(
+            parse(&["--all-images", "a.RAF"]).image_selection(),
+            ImageSelection::All
+        );
+
+        let conflicting = [
+            "rawbit",
+            "-o",
+            "out",
+            "--all-images",
+            "--image-index=1",
+            "a",
+        ];
+        assert!(Cli::try_parse_from(conflicting).is_err());
+    }
+
+    #[test]
+    fn parses_verification_level() {
+        assert_eq!(parse(&["a.RAF"]).verify, None);
+        assert_eq!(parse(&["--verify", "a.RAF"]).verify, Some(Verify::Decode));
+        assert_eq!(
+            parse(&["--verify=pixels", "a.RAF"]).verify,
+            Some(Verify::Pixels)
+        );
+
+        let args = parse(&["--verify=pixels", "--apply-scaling", "a.RAF"]);
+        assert!(args.validate().is_err());
+
+        let unverified = ["rawbit", "-o", "out", "--quarantine-dir", "bad", "a"];
+        assert!(Cli::try_parse_from(unverified).is_err());
+    }
+
+    #[test]
+    fn move_implies_verification() {
+        assert_eq!(
+            parse(&["--move", "a.RAF"]).job_options().verify,
+            Some(Verify::Pixels)
+        );
+        assert_eq!(
+            parse(&["--move", "--apply-scaling", "a.RAF"])
+                .job_options()
+                .verify,
+            Some(Verify::Decode)
+        );
+        assert_eq!(
+            parse(&["--move", "--verify=pixels", "a.RAF"])
+                .job_options()
+                .verify,
+            Some(Verify::Pixels)
+        );
+        assert_eq!(parse(&["a.RAF"]).job_options().verify, None);
+    }
+
+    #[test]
+    fn verify_doesnt_need_convert_args() {
+        for name in ["verify", "verify-manifest"] {
+            let cli = Cli::try_parse_from(["rawbit", name, "a.sha256"]).unwrap();
+
+            assert!(matches!(
+                cli.into_command(),
+                Command::Verify(args) if args.manifest == Path::new("a.sha256")
+            ));
+        }
+
+        assert!(Cli::try_parse_from(["rawbit", "a.RAF"]).is_err());
+        assert!(Cli::try_parse_from(["rawbit", "verify", "-o", "out", "a.sha256"]).is_err());
+    }
+
+    #[test]
+    fn journal_is_written_by_default() {
+        let args = parse(&["a.RAF"]);
+        assert!(args.journal);
+        assert!(args.job_options().hash);
+
+        let args = parse(&["--journal=false", "a.RAF"]);
+        assert!(!args.job_options().hash);
+
+        let cli = Cli::try_parse_from(["rawbit", "undo", "out/a.journal"]).unwrap();
+        assert!(matches!(
+            cli.into_command(),
+            Command::Undo(args) if args.journal == Path::new("out/a.journal")
+        ));
+    }
+
+    #[test]
+    fn rename_never_overwrites() {
+        let parse = |args: &[&str]| {
+            let cli = Cli::try_parse_from(
+                ["rawbit", "rename", "-F", "{camera.model}"]
+                    .iter()
+                    .chain(args),
+            );
+            let Command::Rename(args) = cli.unwrap().into_command() else {
+                panic!("expected rename");
+            };
+
+            args
+        };
+
+        let args = parse(&["-i", "dng"]);
+        assert!(args.validate().is_ok());
+        assert_eq!(args.journal_dir(), Path::new("dng"));
+        assert_eq!(parse(&["a.dng"]).journal_dir(), Path::new("."));
+        assert!(parse(&["--on-collision=overwrite", "a.dng"])
+            .validate()
+            .is_err());
+    }
+
+    #[test]
+    fn convert_is_the_default_command() {
+        let explicit = Cli::try_parse_from(["rawbit", "convert", "-o", "out", "-r", "-i", "raw"]);
+        let explicit = convert_args(explicit.unwrap());
+        let bare = parse(&["-r", "-i", "raw"]);
+
+        assert_eq!(explicit.output_dir, bare.output_dir);
+        assert_eq!(explicit.source.input_dir, bare.source.input_dir);
+        assert!(explicit.recurse && bare.recurse);
+    }
+
+    fn configured(config: &str, args: &[&str]) -> Result<ImportConfig, clap::Error> {
+        let config = toml::from_str::<Config>(config).unwrap();
+        let command_line = iter::once(&"rawbit").chain(args).map(Into::into);
+
+        Cli::from_config(command_line.collect(), || Ok::<_, String>(config)).map(convert_args)
+    }
+
+    const CONFIG: &str = r#"
+        format = "{image.original_filename}"
+        recurse = true
+
+        [profile.wedding]
+        out-dir = "/mnt/wedding"
+        format = "%Y/{camera.model}/{image.original_filename}"
+        n_threads = 4
+        compression = "uncompressed"
+        force = true
+        backup-dir = ["/mnt/a", "/mnt/b"]
+    "#;
+
+    #[test]
+    fn profiles_fill_in_missing_options() {
+        let args = configured(CONFIG, &["--profile", "wedding", "a.RAF"]).unwrap();
+
+        assert_eq!(args.output_dir.as_deref(), Some(Path::new("/mnt/wedding")));
+        assert_eq!(
+            args.fmt_str.as_deref(),
+            Some("%Y/{camera.model}/{image.original_filename}")
+        );
+        assert_eq!(args.n_threads, Some(4));
+        assert_eq!(args.compression, Compression::Uncompressed);
+        assert!(args.recurse);
+        assert_eq!(args.backup_dirs.len(), 2);
+
+        let args = configured(CONFIG, &["-o", "out", "a.RAF"]).unwrap();
+        assert_eq!(args.fmt_str.as_deref(), Some("{image.original_filename}"));
+        assert_eq!(args.compression, Compression::Lossless);
+
+        let args = configured(CONFIG, &["convert", "--profile", "wedding", "a.RAF"]).unwrap();
+        assert_eq!(args.output_dir.as_deref(), Some(Path::new("/mnt/wedding")));
+    }
+
+    #[test]
+    fn command_line_overrides_profiles() {
+        let args = configured(
+            CONFIG,
+            &[
+                "--profile=wedding",
+                "-o",
+                "out",
+                "--recurse=false",
+                "--on-collision=skip",
+                "a.RAF",
+            ],
+        )
+        .unwrap();
+
+        assert_eq!(args.output_dir.as_deref(), Some(Path::new("out")));
+        assert!(!args.recurse);
+        // --force conflicts with --on-collision, so it's left out
+        assert_eq!(args.collision_strategy(), Collision::Skip);
+    }
+
+    #[test]
+    fn rejects_unknown_profiles_and_options() {
+        assert!(configured(CONFIG, &["--profile", "birthday", "-o", "out", "a.RAF"]).is_err());
+        assert!(configured("colour = true", &["-o", "out", "a.RAF"]).is_err());
+        assert!(configured("help = true", &["-o", "out", "a.RAF"]).is_err());
+        assert!(configured("recurse = \"yes\"", &["-o", "out", "a.RAF"]).is_err());
+    }
+
+    #[test]
+    fn only_convert_reads_the_config() {
+        let broken = || Err("couldn't parse rawbit.toml");
+        let argv = |args: &[&str]| args.iter().map(Into::into).collect();
+
+        assert!(Cli::from_config(argv(&["rawbit", "-o", "out", "a.RAF"]), broken).is_err());
+        assert!(
+            Cli::from_config(argv(&["rawbit", "convert", "-o", "out", "a.RAF"]), broken).is_err()
+        );
+        assert!(Cli::from_config(argv(&["rawbit", "undo", "a.journal"]), broken).is_ok());
+        assert!(Cli::from_config(argv(&["rawbit", "verify", "a.manifest"]), broken).is_ok());
+    }
 }
@@ -1,18 +1,25 @@
 use crate::parse;
 
 use std::{
+    cell::RefCell,
     error::Error,
-    fmt::{self, Display},
-    io,
-    path::PathBuf,
+    fmt::{self, Display, Write as _},
+    fs::File,
+    io::{self, BufReader, Read, Seek, SeekFrom},
+    path::{Path, PathBuf},
+    rc::Rc,
 };
 
+use sha2::{Digest as _, Sha256};
+
 #[derive(Debug)]
 pub enum AppError {
     FmtStrParse(parse::Error),
     Io(String, io::Error),
     DirNotFound(String, PathBuf),
     AlreadyExists(String, PathBuf),
+    VerifyFailed(String),
+    JobsFailed(usize),
     #[allow(unused)]
     Other(String, Box<dyn Error + Send + Sync>),
 }
@@ -34,3 +41,122 @@ macro_rules! map_err {
 pub(crate) use map_err;
 
 pub type RawbitResult<T> = std::result::Result<T, AppError>;
+
+pub fn sha256_file(path: &Path) -> io::Result<String> {
+    sha256(BufReader::new(File::open(path)?))
+}
+
+/// Hex-encoded digest of everything that's left in `reader`
+pub fn sha256(mut reader: impl Read) -> io::Result<String> {
+    let mut hasher = Sha256::new();
+    io::copy(&mut reader, &mut hasher)?;
+
+    Ok(hex(hasher))
+}
+
+fn hex(hasher: Sha256) -> String {
+    hasher
+        .finalize()
+        .iter()
+        .fold(String::with_capacity(64), |mut hex, byte| {
+            let _ = write!(hex, "{byte:02x}");
+            hex
+        })
+}
+
+/// Hashes what's read through it, for as long as it's read from the start without gaps
+pub struct HashingReader<R> {
+    inner: R,
+    pos: u64,
+    prefix: Rc<RefCell<HashedPrefix>>,
+}
+
+#[derive(Default)]
+pub struct HashedPrefix {
+    hasher: Sha256,
+    len: u64,
+}
+
+impl<R> HashingReader<R> {
+    pub fn new(inner: R) -> (Self, Rc<RefCell<HashedPrefix>>) {
+        let prefix = Rc::default();
+
+        let reader = Self {
+            inner,
+            pos: 0,
+            prefix: Rc::clone(&prefix),
+        };
+
+        (reader, prefix)
+    }
+}
+
+impl<R: Read> Read for HashingReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let n = self.inner.read(buf)?;
+        let end = self.pos + n as u64;
+        let mut prefix = self.prefix.borrow_mut();
+
+        if (self.pos..end).contains(&prefix.len) {
+            let start = usize::try_from(prefix.len - self.pos).map_err(io::Error::other)?;
+            prefix.hasher.update(&buf[start..n]);
+            prefix.len = end;
+        }
+
+        self.pos = end;
+        Ok(n)
+    }
+}
+
+impl<R: Seek> Seek for HashingReader<R> {
+    fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+        self.pos = self.inner.seek(pos)?;
+        Ok(self.pos)
+    }
+}
+
+impl HashedPrefix {
+    /// Hex-encoded digest of `file`, reading only what wasn't hashed yet
+    pub fn finish(&mut self, mut file: impl Read + Seek) -> io::Result<String> {
+        file.seek(SeekFrom::Start(self.len))?;
+        io::copy(&mut file, &mut self.hasher)?;
+
+        Ok(hex(std::mem::take(&mut self.hasher)))
+    }
+}
+
+/// Makes sure that entries created or renamed in `dir` survive a crash; a no-op on platforms
+/// where directories can't be opened as files
+pub fn sync_dir(dir: &Path) -> io::Result<()> {
+    if cfg!(unix) {
+        File::open(dir)?.sync_all()
+    } else {
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test_common {
+    use std::io::{Cursor, Read as _, Seek as _, SeekFrom};
+
+    use super::{sha256, HashingReader};
+
+    #[test]
+    fn hashing_reader_reads_only_what_was_skipped() {
+        let data = (0..=255).cycle().take(10_000).collect::<Vec<u8>>();
+        let (mut reader, prefix) = HashingReader::new(Cursor::new(&data));
+        let mut buf = [0; 1000];
+
+        reader.read_exact(&mut buf).unwrap();
+        reader.seek(SeekFrom::Start(500)).unwrap();
+        reader.read_exact(&mut buf).unwrap();
+        reader.seek(SeekFrom::Start(8000)).unwrap();
+        reader.read_exact(&mut buf).unwrap();
+
+        assert_eq!(prefix.borrow().len, 1500);
+        assert_eq!(
+            prefix.borrow_mut().finish(Cursor::new(&data)).unwrap(),
+            sha256(data.as_slice()).unwrap()
+        );
+    }
+}
@@ -0,0 +1,201 @@
+// Copyright (c) Carter J. Canedy <cartercanedy42@gmail.com>
+// rawbit is free software, distributable under the terms of the MIT license
+// See https://raw.githubusercontent.com/cartercanedy/rawbit/refs/heads/master/LICENSE.txt
+
+use std::{
+    collections::BTreeMap,
+    env,
+    ffi::OsString,
+    fmt::{self, Display},
+    fs::read_to_string,
+    io, iter,
+    path::PathBuf,
+};
+
+use clap::{parser::ValueSource, Arg, ArgAction, ArgGroup, ArgMatches, Command};
+use serde::Deserialize;
+use toml::{Table, Value};
+
+const LOCAL_FILE: &str = "rawbit.toml";
+
+const RESERVED: &[&str] = &["profile", "help", "version"];
+
+#[derive(Debug)]
+pub enum Error {
+    Read(PathBuf, io::Error),
+    Parse(PathBuf, toml::de::Error),
+    UnknownProfile(String),
+    UnknownOption(String),
+    InvalidValue(String, Value),
+}
+
+impl Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Read(path, err) => write!(f, "couldn't read {}: {err}", path.display()),
+            Self::Parse(path, err) => write!(f, "couldn't parse {}: {err}", path.display()),
+            Self::UnknownProfile(name) => write!(f, "no profile named \"{name}\" is configured"),
+            Self::UnknownOption(key) => {
+                write!(f, "\"{key}\" isn't an option that can be configured")
+            }
+            Self::InvalidValue(key, value) => write!(f, "invalid value for \"{key}\": {value}"),
+        }
+    }
+}
+
+/// Keys are the long names of command line options, i.e. `out-dir` or `format`
+#[derive(Debug, Default, Deserialize)]
+pub struct Config {
+    #[serde(default)]
+    profile: BTreeMap<String, Table>,
+    #[serde(flatten)]
+    options: Table,
+}
+
+impl Config {
+    /// `$XDG_CONFIG_HOME/rawbit/config.toml`, or `%APPDATA%\rawbit\config.toml` on Windows
+    fn global_path() -> Option<PathBuf> {
+        let dir = if cfg!(windows) {
+            env::var_os("APPDATA").map(PathBuf::from)
+        } else {
+            env::var_os("XDG_CONFIG_HOME")
+                .filter(|dir| !dir.is_empty())
+                .map(PathBuf::from)
+                .or_else(|| env::var_os("HOME").map(|home| PathBuf::from(home).join(".config")))
+        };
+
+        dir.map(|dir| dir.join("rawbit").join("config.toml"))
+    }
+
+    /// Options in `./rawbit.toml` replace the global ones, profile by profile
+    pub fn load() -> Result<Self, Error> {
+        let mut config = Self::default();
+
+        for path in Self::global_path().into_iter().chain([LOCAL_FILE.into()]) {
+            let text = match read_to_string(&path) {
+                Ok(text) => text,
+                Err(err) if err.kind() == io::ErrorKind::NotFound => continue,
+                Err(err) => return Err(Error::Read(path, err)),
+            };
+
+            match toml::from_str(&text) {
+                Ok(file) => config.merge(file),
+                Err(err) => return Err(Error::Parse(path, err)),
+            }
+        }
+
+        Ok(config)
+    }
+
+    fn merge(&mut self, other: Self) {
+        self.options.extend(other.options);
+
+        for (name, options) in other.profile {
+            self.profile.entry(name).or_default().extend(options);
+        }
+    }
+
+    fn options(&self, profile: Option<&str>) -> Result<Table, Error> {
+        let mut options = self.options.clone();
+
+        if let Some(name) = profile {
+            let profile = self
+                .profile
+                .get(name)
+                .ok_or_else(|| Error::UnknownProfile(name.into()))?;
+
+            options.extend(profile.clone());
+        }
+
+        Ok(options)
+    }
+
+    /// Options that were given in `matches`, on the command line or through the environment, are
+    /// left out, along with the ones that conflict with them.
+    pub fn args(
+        &self,
+        cmd: &Command,
+        matches: &ArgMatches,
+        profile: Option<&str>,
+    ) -> Result<Vec<OsString>, Error> {
+        let explicit = |arg: &Arg| {
+            matches!(
+                matches.value_source(arg.get_id().as_str()),
+                Some(ValueSource::CommandLine | ValueSource::EnvVariable)
+            )
+        };
+
+        let mut args = vec![];
+
+        for (key, value) in self.options(profile)? {
+            let long = key.replace('_', "-");
+            let arg = cmd
+                .get_arguments()
+                .find(|arg| arg.get_long() == Some(long.as_str()))
+                .filter(|_| !RESERVED.contains(&long.as_str()))
+                .ok_or_else(|| Error::UnknownOption(key.clone()))?;
+
+            let overridden = cmd
+                .get_arguments()
+                .filter(|other| explicit(other))
+                .any(|other| other == arg || conflicts(cmd, arg, other));
+
+            if !overridden {
+                push_arg(&mut args, arg, &long, &key, &value)?;
+            }
+        }
+
+        Ok(args)
+    }
+}
+
+fn conflicts(cmd: &Command, a: &Arg, b: &Arg) -> bool {
+    let in_group = |group: &ArgGroup| {
+        group.get_args().any(|id| id == a.get_id()) && group.get_args().any(|id| id == b.get_id())
+    };
+
+    cmd.get_arg_conflicts_with(a).contains(&b)
+        || cmd.get_arg_conflicts_with(b).contains(&a)
+        || cmd
+            .get_groups()
+            .any(|group| !group.clone().is_multiple() && in_group(group))
+}
+
+fn push_arg(
+    args: &mut Vec<OsString>,
+    arg: &Arg,
+    long: &str,
+    key: &str,
+    value: &Value,
+) -> Result<(), Error> {
+    let invalid = || Error::InvalidValue(key.into(), value.clone());
+
+    match (value, arg.get_action()) {
+        (Value::Boolean(set), ArgAction::SetTrue) => {
+            if *set {
+                args.push(format!("--{long}").into());
+            }
+        }
+
+        (&Value::Integer(n), ArgAction::Count) => {
+            let n = usize::try_from(n).map_err(|_| invalid())?;
+            args.extend(iter::repeat_n(format!("--{long}").into(), n));
+        }
+
+        (Value::Array(values), ArgAction::Append) => {
+            for value in values {
+                push_arg(args, arg, long, key, value)?;
+            }
+        }
+
+        (Value::String(s), _) => args.push(format!("--{long}={s}").into()),
+        (Value::Boolean(b), ArgAction::Set) => args.push(format!("--{long}={b}").into()),
+        (Value::Integer(n), ArgAction::Set | ArgAction::Append) => {
+            args.push(format!("--{long}={n}").into());
+        }
+
+        _ => return Err(invalid()),
+    }
+
+    Ok(())
+}
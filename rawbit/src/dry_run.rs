@@ -0,0 +1,285 @@
+// Copyright (c) Carter J. Canedy <cartercanedy42@gmail.com>
+// rawbit is free software, distributable under the terms of the MIT license
+// See https://raw.githubusercontent.com/cartercanedy/rawbit/refs/heads/master/LICENSE.txt
+
+use std::{
+    collections::HashMap,
+    fmt::Write as _,
+    path::{Path, PathBuf},
+};
+
+use serde::Serialize;
+
+use crate::{
+    events::{self, Event},
+    job::Error,
+    parse::{Counters, FilenameFormat, RenderContext},
+    plan::{Conflict, Planned, Source},
+};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Action {
+    Write,
+    Overwrite,
+    Skip,
+    Fail,
+}
+
+impl Action {
+    const fn label(self) -> &'static str {
+        match self {
+            Self::Write => "write",
+            Self::Overwrite => "overwrite",
+            Self::Skip => "skip",
+            Self::Fail => "fail",
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct Row<'a> {
+    pub input_path: &'a Path,
+    pub output_path: Option<&'a Path>,
+    pub action: Action,
+    pub notes: Vec<String>,
+}
+
+/// The output paths as they were rendered, before collisions were resolved
+#[derive(Debug, Default)]
+pub struct Rendered {
+    paths: HashMap<(PathBuf, usize), PathBuf>,
+    counts: HashMap<PathBuf, usize>,
+}
+
+impl Rendered {
+    pub fn new(planned: &[Planned]) -> Self {
+        let mut rendered = Self::default();
+
+        for planned in planned {
+            let key = (
+                planned.source.input_path.clone(),
+                planned.source.image.index,
+            );
+            rendered.paths.insert(key, planned.output_path.clone());
+            *rendered
+                .counts
+                .entry(planned.output_path.clone())
+                .or_default() += 1;
+        }
+
+        rendered
+    }
+
+    fn notes(&self, source: &Source, output_path: Option<&Path>, notes: &mut Vec<String>) {
+        let key = (source.input_path.clone(), source.image.index);
+        let Some(rendered) = self.paths.get(&key) else {
+            return;
+        };
+
+        match self.counts.get(rendered).copied().unwrap_or_default() {
+            0 | 1 => {}
+            2 => notes.push("collides with 1 other image".into()),
+            n => notes.push(format!("collides with {} other images", n - 1)),
+        }
+
+        if rendered.exists() {
+            notes.push("file exists".into());
+        }
+
+        if output_path.is_some_and(|path| path != rendered) {
+            notes.push(format!("renamed from {}", rendered.display()));
+        }
+    }
+}
+
+fn backups(planned: &Planned, action: Action) -> Vec<String> {
+    if action == Action::Fail {
+        return vec![];
+    }
+
+    planned
+        .backup_paths
+        .iter()
+        .map(|path| {
+            if path.exists() && !planned.overwrite {
+                format!("backup exists: {}", path.display())
+            } else {
+                format!("backup to {}", path.display())
+            }
+        })
+        .collect()
+}
+
+fn missing(source: &Source, format: &FilenameFormat) -> Option<String> {
+    let ctx = RenderContext {
+        original_filename: &source.original_filename(),
+        md: &source.md,
+        image: source.image,
+        counters: Counters::default(),
+    };
+
+    let missing = format.missing(&ctx);
+    (!missing.is_empty()).then(|| format!("missing {}", missing.join(", ")))
+}
+
+pub fn rows<'a>(
+    planned: &'a [Planned],
+    conflicts: &'a [Conflict],
+    unreadable: &'a [(PathBuf, Error)],
+    rendered: &Rendered,
+    format: &FilenameFormat,
+) -> Vec<Row<'a>> {
+    let planned_rows = planned.iter().map(|planned| {
+        let action = if planned.overwrite {
+            Action::Overwrite
+        } else {
+            Action::Write
+        };
+
+        (planned, action, None)
+    });
+
+    let conflict_rows = conflicts.iter().map(|conflict| match conflict {
+        Conflict::Skipped(planned, reason) | Conflict::Exists(planned, reason) => {
+            (planned, Action::Skip, Some(reason.clone()))
+        }
+        Conflict::Failed(planned, err) => (planned, Action::Fail, Some(err.message().into())),
+    });
+
+    let mut rows = planned_rows
+        .chain(conflict_rows)
+        .map(|(planned, action, reason)| {
+            let output_path = (action != Action::Fail).then_some(planned.output_path.as_path());
+            let mut notes = reason.into_iter().collect();
+
+            rendered.notes(&planned.source, output_path, &mut notes);
+            notes.extend(backups(planned, action));
+            notes.extend(missing(&planned.source, format));
+
+            Row {
+                input_path: &planned.source.input_path,
+                output_path,
+                action,
+                notes,
+            }
+        })
+        .collect::<Vec<_>>();
+
+    rows.extend(unreadable.iter().map(|(input_path, err)| Row {
+        input_path,
+        output_path: None,
+        action: Action::Fail,
+        notes: vec![err.message().into()],
+    }));
+
+    rows
+}
+
+pub fn table(rows: &[Row]) -> String {
+    let display =
+        |path: Option<&Path>| path.map_or_else(|| "-".into(), |p| p.display().to_string());
+
+    let sources = rows
+        .iter()
+        .map(|row| display(Some(row.input_path)))
+        .collect::<Vec<_>>();
+    let outputs = rows
+        .iter()
+        .map(|row| display(row.output_path))
+        .collect::<Vec<_>>();
+
+    let width = |column: &[String]| column.iter().map(|s| s.chars().count()).max();
+    let source_width = width(&sources).unwrap_or_default().max("SOURCE".len());
+    // the destinations are preceded by an arrow
+    let output_width = width(&outputs).unwrap_or_default().max("DESTINATION".len()) + 2;
+
+    let mut out = String::new();
+    let mut line = |action: &str, source: &str, output: &str, notes: &str| {
+        let _ = writeln!(
+            out,
+            "{}",
+            format!("{action:<10}{source:<source_width$}  {output:<output_width$}  {notes}")
+                .trim_end()
+        );
+    };
+
+    line("ACTION", "SOURCE", "  DESTINATION", "NOTES");
+
+    for ((row, source), output) in rows.iter().zip(&sources).zip(&outputs) {
+        line(
+            row.action.label(),
+            source,
+            &format!("→ {output}"),
+            &row.notes.join("; "),
+        );
+    }
+
+    let count = |action| rows.iter().filter(|row| row.action == action).count();
+    let _ = writeln!(
+        out,
+        "\ndry run: {} to write, {} to overwrite, {} to skip, {} failing; nothing was written",
+        count(Action::Write),
+        count(Action::Overwrite),
+        count(Action::Skip),
+        count(Action::Fail),
+    );
+
+    out
+}
+
+pub fn report(rows: &[Row]) {
+    if events::enabled() {
+        for row in rows {
+            events::emit(&Event::Planned {
+                input_path: row.input_path.to_string_lossy(),
+                output_path: row.output_path.map(Path::to_string_lossy),
+                action: row.action,
+                notes: &row.notes,
+            });
+        }
+    } else {
+        print!("{}", table(rows));
+    }
+}
+
+#[cfg(test)]
+mod test_dry_run {
+    use std::path::Path;
+
+    use super::{table, Action, Row};
+
+    #[test]
+    fn lines_up_columns_and_counts_actions() {
+        let rows = [
+            Row {
+                input_path: Path::new("raw/DSC01234.ARW"),
+                output_path: Some(Path::new("out/2024/DSC01234.dng")),
+                action: Action::Write,
+                notes: vec![],
+            },
+            Row {
+                input_path: Path::new("raw/B.ARW"),
+                output_path: None,
+                action: Action::Fail,
+                notes: vec![
+                    "won't overwrite existing file".into(),
+                    "missing lens.model".into(),
+                ],
+            },
+        ];
+
+        let table = table(&rows);
+        let lines = table.lines().collect::<Vec<_>>();
+
+        assert_eq!(
+            lines[..3],
+            [
+                "ACTION    SOURCE              DESTINATION            NOTES",
+                "write     raw/DSC01234.ARW  → out/2024/DSC01234.dng",
+                "fail      raw/B.ARW         → -                      won't overwrite existing file; missing lens.model",
+            ]
+        );
+        assert!(table.ends_with("0 to skip, 1 failing; nothing was written\n"));
+    }
+}
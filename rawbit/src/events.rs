@@ -0,0 +1,183 @@
+// Copyright (c) Carter J. Canedy <cartercanedy42@gmail.com>
+// rawbit is free software, distributable under the terms of the MIT license
+// See https://raw.githubusercontent.com/cartercanedy/rawbit/refs/heads/master/LICENSE.txt
+
+use std::{
+    borrow::Cow,
+    collections::BTreeMap,
+    io::{self, Write as _},
+    path::Path,
+    sync::OnceLock,
+};
+
+use clap::ValueEnum;
+use rawler::exif::Exif;
+use serde::Serialize;
+
+use crate::{
+    dry_run::Action,
+    job::Error,
+    summary::{Failure, Summary},
+};
+
+static FORMAT: OnceLock<OutputFormat> = OnceLock::new();
+
+/// How rawbit reports progress on stdout
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, ValueEnum)]
+pub enum OutputFormat {
+    /// human readable log messages
+    #[default]
+    Text,
+    /// one JSON event per line; log messages are turned off
+    Jsonl,
+}
+
+impl OutputFormat {
+    /// Only the first call has any effect
+    pub fn init(self) {
+        let _ = FORMAT.set(self);
+    }
+
+    pub fn get() -> Self {
+        FORMAT.get().copied().unwrap_or_default()
+    }
+}
+
+/// Paths are converted lossily, so that every event is valid JSON
+#[derive(Debug, Serialize)]
+#[serde(tag = "event", rename_all = "snake_case")]
+pub enum Event<'a> {
+    Found {
+        path: Cow<'a, str>,
+        supported: bool,
+    },
+    Started {
+        input_path: Cow<'a, str>,
+        output_path: Cow<'a, str>,
+        image_index: usize,
+    },
+    Finished {
+        input_path: Cow<'a, str>,
+        output_path: Cow<'a, str>,
+        backup_paths: Vec<Cow<'a, str>>,
+        input_size: Option<u64>,
+        output_size: Option<u64>,
+        duration_ms: u128,
+    },
+    Skipped {
+        input_path: Cow<'a, str>,
+        output_path: Cow<'a, str>,
+        reason: &'a str,
+    },
+    Failed {
+        input_path: Cow<'a, str>,
+        output_path: Option<Cow<'a, str>>,
+        /// see [`Failure`]
+        kind: &'static str,
+        message: &'a str,
+        cause: Option<String>,
+    },
+    Inspected {
+        input_path: Cow<'a, str>,
+        image_index: usize,
+        tokens: BTreeMap<String, Option<String>>,
+        exif: &'a Exif,
+    },
+    Planned {
+        input_path: Cow<'a, str>,
+        output_path: Option<Cow<'a, str>>,
+        action: Action,
+        notes: &'a [String],
+    },
+    Summary {
+        written: usize,
+        skipped: usize,
+        exists: usize,
+        unsupported: usize,
+        failed: usize,
+        failures: BTreeMap<&'static str, usize>,
+        aborted: usize,
+    },
+    /// the run was stopped by an error that isn't specific to one image
+    Fatal {
+        message: &'a str,
+        exit_code: u8,
+    },
+}
+
+impl<'a> Event<'a> {
+    pub fn failed(input_path: &'a Path, output_path: Option<&'a Path>, err: &'a Error) -> Self {
+        Self::Failed {
+            input_path: input_path.to_string_lossy(),
+            output_path: output_path.map(Path::to_string_lossy),
+            kind: Failure::of(err).label(),
+            message: err.message(),
+            cause: err.cause().map(ToString::to_string),
+        }
+    }
+
+    pub fn summary(summary: &Summary) -> Self {
+        Self::Summary {
+            written: summary.written,
+            skipped: summary.skipped,
+            exists: summary.exists,
+            unsupported: summary.unsupported,
+            failed: summary.failures(),
+            failures: summary
+                .failed()
+                .map(|(failure, n)| (failure.label(), n))
+                .collect(),
+            aborted: summary.aborted,
+        }
+    }
+}
+
+pub fn enabled() -> bool {
+    OutputFormat::get() == OutputFormat::Jsonl
+}
+
+pub fn emit(event: &Event) {
+    if !enabled() {
+        return;
+    }
+
+    let Ok(line) = serde_json::to_string(event) else {
+        return;
+    };
+
+    // the lock keeps lines from concurrent jobs from interleaving
+    let _ = writeln!(io::stdout().lock(), "{line}");
+}
+
+#[cfg(test)]
+mod test_events {
+    use std::path::Path;
+
+    use super::Event;
+    use crate::{job::Error, summary::Summary};
+
+    #[test]
+    fn failures_carry_kind_and_message() {
+        let err = Error::VerifyFailed("DNG dimensions don't match the source".into());
+        let event = Event::failed(Path::new("a/IMG_0001.CR3"), None, &err);
+
+        assert_eq!(
+            serde_json::to_string(&event).unwrap(),
+            r#"{"event":"failed","input_path":"a/IMG_0001.CR3","output_path":null,"kind":"verify","message":"DNG dimensions don't match the source","cause":null}"#
+        );
+    }
+
+    #[test]
+    fn summary_counts_failures_by_kind() {
+        let mut summary = Summary::default();
+        summary.finished::<()>(&Err(Error::AlreadyExists(String::new())));
+        summary.finished(&Ok(()));
+
+        let json = serde_json::to_value(Event::summary(&summary)).unwrap();
+
+        assert_eq!(json["event"], "summary");
+        assert_eq!(json["written"], 1);
+        assert_eq!(json["failed"], 1);
+        assert_eq!(json["failures"]["exists"], 1);
+    }
+}
@@ -0,0 +1,87 @@
+// Copyright (c) Carter J. Canedy <cartercanedy42@gmail.com>
+// rawbit is free software, distributable under the terms of the MIT license
+// See https://raw.githubusercontent.com/cartercanedy/rawbit/refs/heads/master/LICENSE.txt
+
+use std::{
+    fs::File,
+    io::{BufWriter, Write as _},
+    path::PathBuf,
+};
+
+use rawler::{
+    decoders::{Camera, RawMetadata},
+    dng::{
+        writer::DngWriter, CropMode, DngCompression, DngPhotometricConversion, DNG_VERSION_V1_4,
+    },
+    exif::Exif,
+    pixarray::PixU16,
+    rawimage::{BlackLevel, CFAConfig, RawPhotometricInterpretation, WhiteLevel},
+    RawImage, CFA,
+};
+
+use crate::{
+    parse::{capture_time, ImageInfo},
+    plan::Source,
+};
+
+pub fn metadata(make: &str, model: &str, exif: Exif) -> RawMetadata {
+    RawMetadata {
+        exif,
+        model: model.into(),
+        make: make.into(),
+        lens: None,
+        unique_image_id: None,
+        rating: None,
+    }
+}
+
+pub fn source(input_path: impl Into<PathBuf>, md: RawMetadata) -> Source {
+    Source {
+        input_path: input_path.into(),
+        output_prefix: PathBuf::new(),
+        captured: capture_time(&md),
+        image: ImageInfo::default(),
+        md,
+    }
+}
+
+const WIDTH: usize = 64;
+const HEIGHT: usize = 48;
+
+/// The CFA pattern is stored relative to the active area, so "RGGB" is written as "BGGR"
+pub fn write_dng(file: &mut File, cfa: &str, pixel: impl Fn(u16) -> u16) {
+    let mut cam = Camera::new();
+    cam.make = "rawbit".into();
+    cam.model = "test".into();
+    cam.cfa = CFA::new(cfa);
+    cam.active_area = Some([1, 1, 1, 1]);
+
+    let data = (0..).take(WIDTH * HEIGHT).map(pixel).collect();
+    let photometric = RawPhotometricInterpretation::Cfa(CFAConfig::new_from_camera(&cam));
+    let image = RawImage::new(
+        cam,
+        PixU16::new_with(data, WIDTH, HEIGHT),
+        1,
+        [1.0; 4],
+        photometric,
+        Some(BlackLevel::new(&[0_u32], 1, 1, 1)),
+        Some(WhiteLevel::new_bits(12, 1)),
+        false,
+    );
+
+    let mut writer = BufWriter::new(file);
+    let mut dng = DngWriter::new(&mut writer, DNG_VERSION_V1_4).unwrap();
+    let mut raw = dng.subframe(0);
+    raw.raw_image(
+        &image,
+        CropMode::ActiveArea,
+        DngCompression::Uncompressed,
+        DngPhotometricConversion::Original,
+        1,
+    )
+    .unwrap();
+    raw.finalize().unwrap();
+    dng.load_base_tags(&image).unwrap();
+    dng.close().unwrap();
+    writer.flush().unwrap();
+}
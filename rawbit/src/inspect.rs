@@ -0,0 +1,230 @@
+// Copyright (c) Carter J. Canedy <cartercanedy42@gmail.com>
+// rawbit is free software, distributable under the terms of the MIT license
+// See https://raw.githubusercontent.com/cartercanedy/rawbit/refs/heads/master/LICENSE.txt
+
+use std::fmt::Write as _;
+
+use serde_json::Value;
+
+use crate::{
+    events::{self, Event},
+    parse::{Counters, MetadataKind, RenderContext},
+    plan::{self, Source},
+};
+
+/// Stands in for the `%...` date/time specifiers, which all draw from the capture time
+const CAPTURE_TIME_TOKEN: &str = "%Y-%m-%d %H:%M:%S";
+
+/// `{seq.dir}` is left out, since it depends on a format and an output directory that
+/// `inspect` doesn't have
+pub fn tokens(source: &Source, counters: Counters) -> Vec<(String, Option<String>)> {
+    let ctx = RenderContext {
+        original_filename: &source.original_filename(),
+        md: &source.md,
+        image: source.image,
+        counters,
+    };
+
+    let expansions = MetadataKind::ALL.into_iter().map(|kind| {
+        let value = kind.expand_with_metadata(&ctx);
+        (
+            format!("{{{}}}", kind.name()),
+            (!value.is_empty()).then(|| value.into_owned()),
+        )
+    });
+
+    let captured = source
+        .captured
+        .map(|captured| captured.format(CAPTURE_TIME_TOKEN).to_string());
+
+    let seq = [("seq", counters.run), ("seq.day", counters.day)]
+        .map(|(name, n)| (format!("{{{name}}}"), Some(n.to_string())));
+
+    expansions
+        .chain(seq)
+        .chain([(CAPTURE_TIME_TOKEN.into(), captured)])
+        .collect()
+}
+
+pub fn exif(source: &Source) -> Vec<(String, String)> {
+    fn flatten(name: String, value: Value, rows: &mut Vec<(String, String)>) {
+        match value {
+            Value::Null => {}
+            Value::Object(fields) => {
+                for (field, value) in fields {
+                    flatten(format!("{name}.{field}"), value, rows);
+                }
+            }
+            Value::Array(values) => {
+                let values = values.iter().map(scalar).collect::<Vec<_>>();
+                rows.push((name, values.join(", ")));
+            }
+            value => rows.push((name, scalar(&value))),
+        }
+    }
+
+    fn scalar(value: &Value) -> String {
+        match value {
+            Value::String(s) => s.clone(),
+            value => value.to_string(),
+        }
+    }
+
+    let mut rows = vec![];
+
+    if let Ok(value) = serde_json::to_value(&source.md.exif) {
+        flatten("exif".into(), value, &mut rows);
+    }
+
+    rows
+}
+
+pub fn table(source: &Source, counters: Counters) -> String {
+    let tokens = tokens(source, counters);
+    let exif = exif(source);
+
+    let width = tokens
+        .iter()
+        .map(|(name, _)| name)
+        .chain(exif.iter().map(|(name, _)| name))
+        .map(|name| name.chars().count())
+        .max()
+        .unwrap_or_default();
+
+    let mut out = format!(
+        "{} (image {})\n",
+        source.input_path.display(),
+        source.image.index
+    );
+
+    let values = tokens
+        .iter()
+        .map(|(name, value)| (name, value.as_deref().unwrap_or("(none)")));
+
+    for (name, value) in values.chain(exif.iter().map(|(name, value)| (name, value.as_str()))) {
+        let _ = writeln!(out, "  {name:<width$}  {value}");
+    }
+
+    out
+}
+
+/// The images are numbered for `{seq}` and `{seq.day}` as if they were converted together
+pub fn report(sources: &[Source]) {
+    let counters = plan::counters(sources);
+
+    for (n, (source, &counters)) in sources.iter().zip(&counters).enumerate() {
+        if events::enabled() {
+            events::emit(&Event::Inspected {
+                input_path: source.input_path.to_string_lossy(),
+                image_index: source.image.index,
+                tokens: tokens(source, counters).into_iter().collect(),
+                exif: &source.md.exif,
+            });
+        } else {
+            if n > 0 {
+                println!();
+            }
+
+            print!("{}", table(source, counters));
+        }
+    }
+}
+
+#[cfg(test)]
+mod test_inspect {
+    use rawler::{
+        exif::{Exif, ExifGPS},
+        formats::tiff::Rational,
+    };
+
+    use super::{exif, table, tokens};
+    use crate::{
+        fixtures,
+        parse::{Counters, ImageInfo},
+        plan::Source,
+    };
+
+    fn source() -> Source {
+        let md = fixtures::metadata(
+            "Fujifilm",
+            "X-T5",
+            Exif {
+                date_time_original: Some("2024:06:01 14:30:05".into()),
+                fnumber: Some(Rational::new(28, 10)),
+                lens_model: Some("XF23mmF1.4 R LM WR".into()),
+                gps: Some(ExifGPS {
+                    gps_latitude: Some([
+                        Rational::new(52, 1),
+                        Rational::new(31, 1),
+                        Rational::new(0, 1),
+                    ]),
+                    ..Default::default()
+                }),
+                ..Default::default()
+            },
+        );
+
+        Source {
+            image: ImageInfo {
+                width: 7728,
+                height: 5152,
+                bit_depth: 14,
+                index: 0,
+            },
+            ..fixtures::source("raw/DSCF0001.RAF", md)
+        }
+    }
+
+    #[test]
+    fn lists_every_token_with_its_value() {
+        let counters = Counters {
+            run: 3,
+            day: 2,
+            dir: 0,
+        };
+        let tokens = tokens(&source(), counters);
+        let value = |name: &str| {
+            tokens
+                .iter()
+                .find(|(token, _)| token == name)
+                .unwrap()
+                .1
+                .clone()
+        };
+
+        assert_eq!(tokens.len(), 21);
+        assert_eq!(value("{camera.model}").as_deref(), Some("X-T5"));
+        assert_eq!(value("{lens.fstop}").as_deref(), Some("2.8"));
+        assert_eq!(
+            value("{image.original_filename}").as_deref(),
+            Some("DSCF0001")
+        );
+        assert_eq!(value("{lens.make}"), None);
+        assert_eq!(value("{seq}").as_deref(), Some("3"));
+        assert_eq!(value("{seq.day}").as_deref(), Some("2"));
+        assert_eq!(
+            value("%Y-%m-%d %H:%M:%S").as_deref(),
+            Some("2024-06-01 14:30:05")
+        );
+    }
+
+    #[test]
+    fn flattens_exif_fields() {
+        let exif = exif(&source());
+
+        assert!(exif.contains(&("exif.fnumber".into(), "28/10".into())));
+        assert!(exif.contains(&("exif.gps.gps_latitude".into(), "52/1, 31/1, 0/1".into())));
+        assert!(!exif.iter().any(|(name, _)| name == "exif.artist"));
+    }
+
+    #[test]
+    fn table_lines_up_values() {
+        let table = table(&source(), Counters::default());
+        let lines = table.lines().collect::<Vec<_>>();
+
+        assert_eq!(lines[0], "raw/DSCF0001.RAF (image 0)");
+        assert_eq!(lines[1].find("Fujifilm"), lines[2].find("X-T5"));
+        assert!(table.contains("  {lens.make}  "));
+        assert!(table.contains("(none)\n"));
+    }
+}
@@ -1,85 +1,163 @@
 use std::{
+    borrow::Cow,
     error,
-    fs::{create_dir_all, remove_file, OpenOptions},
-    io::{self, BufReader, BufWriter},
-    path::PathBuf,
+    fmt::Display,
+    fs::{File, OpenOptions},
+    io::{self, BufReader, BufWriter, Read, Seek, Write},
+    path::{Path, PathBuf},
 };
 
+use clap::ValueEnum;
 use rawler::{
-    decoders::{RawDecodeParams, RawMetadata},
-    dng::{self, convert::ConvertParams},
-    get_decoder, RawFile, RawlerError,
+    dng::{self, convert::ConvertParams, CropMode, DngCompression},
+    RawlerError,
 };
 
-use smlog::info;
+use smlog::{debug, info, warn};
 
-use crate::{common::map_err, parse::FilenameFormat};
+use crate::{
+    common::{map_err, sha256, sha256_file, HashingReader},
+    output::AtomicFile,
+    progress,
+    verify::{verify, Verify},
+};
 
 #[derive(Debug)]
 pub enum Error {
     ImgOp(String, RawlerError),
     Io(String, io::Error),
     AlreadyExists(String),
+    NotFound(String),
+    VerifyFailed(String),
     #[allow(unused)]
     Other(String, Box<dyn error::Error + Send + Sync>),
 }
 
+impl Error {
+    /// Describes what went wrong, without the underlying cause
+    pub fn message(&self) -> &str {
+        match self {
+            Self::ImgOp(msg, _)
+            | Self::Io(msg, _)
+            | Self::Other(msg, _)
+            | Self::AlreadyExists(msg)
+            | Self::NotFound(msg)
+            | Self::VerifyFailed(msg) => msg,
+        }
+    }
+
+    pub fn cause(&self) -> Option<&dyn Display> {
+        match self {
+            Self::ImgOp(_, cause) => Some(cause),
+            Self::Io(_, cause) => Some(cause),
+            Self::Other(_, cause) => Some(cause),
+            Self::AlreadyExists(_) | Self::NotFound(_) | Self::VerifyFailed(_) => None,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, ValueEnum)]
+pub enum Compression {
+    /// lossless JPEG-92
+    #[default]
+    Lossless,
+    /// no compression, largest output
+    Uncompressed,
+}
+
+impl From<Compression> for DngCompression {
+    fn from(compression: Compression) -> Self {
+        match compression {
+            Compression::Lossless => Self::Lossless,
+            Compression::Uncompressed => Self::Uncompressed,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, ValueEnum)]
+pub enum Crop {
+    /// crop to the active area and record the camera's default crop
+    #[default]
+    Best,
+    /// crop to the active area, i.e. remove masked border pixels
+    #[value(name = "activearea")]
+    ActiveArea,
+    /// keep every pixel the sensor recorded
+    None,
+}
+
+impl From<Crop> for CropMode {
+    fn from(crop: Crop) -> Self {
+        match crop {
+            Crop::Best => Self::Best,
+            Crop::ActiveArea => Self::ActiveArea,
+            Crop::None => Self::None,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Mode {
+    #[default]
+    Convert,
+    Copy,
+}
+
+impl Mode {
+    /// Includes the dot
+    pub fn extension(self, input_path: &Path) -> Cow<'static, str> {
+        match (self, input_path.extension()) {
+            (Self::Convert, _) => Cow::Borrowed(".dng"),
+            (Self::Copy, Some(ext)) => Cow::Owned(format!(".{}", ext.to_string_lossy())),
+            (Self::Copy, None) => Cow::Borrowed(""),
+        }
+    }
+}
+
+#[derive(Clone)]
+pub struct Options {
+    pub mode: Mode,
+    pub convert: ConvertParams,
+    pub verify: Option<Verify>,
+    /// where outputs that fail verification are moved to, instead of being deleted
+    pub quarantine_dir: Option<PathBuf>,
+    pub hash: bool,
+}
+
+#[derive(Clone, Debug)]
+pub struct Hashes {
+    pub source: String,
+    pub output: String,
+}
+
 pub struct Job {
     pub input_path: PathBuf,
-    pub output_dir: PathBuf,
-    pub filename_format: &'static FilenameFormat<'static>,
-    pub force: bool,
-    pub convert_opts: ConvertParams,
+    pub output_path: PathBuf,
+    pub backup_paths: Vec<PathBuf>,
+    pub overwrite: bool,
+    pub opts: Options,
 }
 
 impl Job {
     pub fn new(
         input_path: PathBuf,
-        output_dir: PathBuf,
-        filename_format: &'static FilenameFormat,
-        force: bool,
-        convert_opts: ConvertParams,
+        output_path: PathBuf,
+        backup_paths: Vec<PathBuf>,
+        overwrite: bool,
+        opts: Options,
     ) -> Self {
         assert!(input_path.is_file());
 
         Self {
             input_path,
-            output_dir,
-            filename_format,
-            force,
-            convert_opts,
+            output_path,
+            backup_paths,
+            overwrite,
+            opts,
         }
     }
 
-    fn build_output_path(&self, md: &RawMetadata) -> Result<PathBuf, Error> {
-        let input_filename_root = self
-            .input_path
-            .file_stem()
-            .unwrap_or_else(|| {
-                panic!(
-                    "couldn't deduce filename from {}",
-                    self.input_path.display()
-                )
-            })
-            .to_string_lossy();
-
-        let output_filename = self
-            .filename_format
-            .render_filename(input_filename_root.as_ref(), md)
-            + ".dng";
-
-        map_err!(
-            create_dir_all(&self.output_dir),
-            Error::Io,
-            format!("couldn't make output dir: {}", self.output_dir.display())
-        )?;
-
-        let output_path = self.output_dir.join(output_filename);
-
-        Ok(output_path)
-    }
-
-    fn run_blocking(self) -> Result<(), Error> {
+    fn run_blocking(self) -> Result<Option<Hashes>, Error> {
         let input = map_err!(
             OpenOptions::new()
                 .read(true)
@@ -89,70 +167,312 @@ impl Job {
             "Couldn't open input RAW file",
         )?;
 
-        let mut raw_file = RawFile::new(self.input_path.as_path(), BufReader::new(input));
+        // every destination is claimed up front, so that a taken backup path fails the job before
+        // anything is converted
+        let mut output = AtomicFile::create(&self.output_path, self.overwrite)?;
+        let backups = self
+            .backup_paths
+            .iter()
+            .map(|path| AtomicFile::create(path, self.overwrite))
+            .collect::<Result<Vec<_>, _>>()?;
 
-        let decoder = map_err!(
-            get_decoder(&mut raw_file),
-            Error::ImgOp,
-            "no compatible RAW image decoder available",
-        )?;
+        // the source is hashed while it's read, so that only what the conversion skipped is read
+        // again
+        let source_hash = if self.opts.hash {
+            let rest = map_err!(input.try_clone(), Error::Io, "couldn't hash input RAW file")?;
+            let (reader, prefix) = HashingReader::new(BufReader::new(input));
+            self.write(reader, output.as_file_mut())?;
 
-        let md = map_err!(
-            decoder.raw_metadata(&mut raw_file, RawDecodeParams::default()),
-            Error::ImgOp,
-            "couldn't extract image metadata",
-        )?;
+            let hash = prefix.borrow_mut().finish(BufReader::new(rest));
+            Some(map_err!(hash, Error::Io, "couldn't hash input RAW file")?)
+        } else {
+            self.write(BufReader::new(input), output.as_file_mut())?;
+            None
+        };
+
+        let mut output = self.check(output, &self.output_path)?;
+
+        let hashes = match source_hash {
+            // a copy holds the same bytes that were hashed while they were read
+            Some(source) if self.opts.mode == Mode::Copy => Some(Hashes {
+                output: source.clone(),
+                source,
+            }),
+
+            Some(source) => {
+                let file = output.as_file_mut();
+                map_err!(file.rewind(), Error::Io, "couldn't read back output file")?;
 
-        map_err!(raw_file.file.rewind(), Error::Io, "input file io error",)?;
-
-        let output_path = self.build_output_path(&md)?;
-
-        if output_path.exists() {
-            if !self.force {
-                Err(Error::AlreadyExists(format!(
-                    "won't overwrite existing file: {}",
-                    output_path.display()
-                )))
-            } else if output_path.is_dir() {
-                Err(Error::AlreadyExists(format!(
-                    "computed filepath already exists as a directory: {}",
-                    output_path.display()
-                )))
-            } else {
-                map_err!(
-                    remove_file(&output_path),
+                let output = map_err!(
+                    sha256(BufReader::new(file)),
                     Error::Io,
-                    format!("couldn't remove existing file: {}", output_path.display()),
-                )
-            }?;
+                    "couldn't hash output file"
+                )?;
+
+                Some(Hashes { source, output })
+            }
+
+            None => None,
+        };
+
+        let backups = backups
+            .into_iter()
+            .zip(&self.backup_paths)
+            .map(|(mut backup, path)| {
+                mirror(output.as_file_mut(), backup.as_file_mut(), path)?;
+                self.check(backup, path)
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+
+        output.commit()?;
+        backups.into_iter().try_for_each(AtomicFile::commit)?;
+
+        Ok(hashes)
+    }
+
+    /// Quarantines the output if it fails verification
+    fn check(&self, mut output: AtomicFile, output_path: &Path) -> Result<AtomicFile, Error> {
+        let Some(level) = self.opts.verify else {
+            return Ok(output);
+        };
+
+        debug!("Verifying output: \"{}\"", output_path.display());
+
+        let verified = match self.opts.mode {
+            Mode::Convert => verify(
+                &self.input_path,
+                output.as_file_mut(),
+                &self.opts.convert,
+                level,
+            ),
+            Mode::Copy => verify_copy(&self.input_path, output.as_file_mut()),
+        };
+
+        if let Err(err) = verified {
+            if let Some(dir) = &self.opts.quarantine_dir {
+                match output.quarantine(dir) {
+                    Ok(path) => progress::suspend(|| {
+                        warn!("moved unverified output to \"{}\"", path.display());
+                    }),
+                    Err(quarantine_err) => progress::suspend(|| {
+                        warn!("couldn't quarantine unverified output: {quarantine_err:?}");
+                    }),
+                }
+            }
+
+            return Err(err);
         }
 
-        let output_file = OpenOptions::new()
-            .write(true)
-            .create_new(true)
-            .open(&output_path);
+        Ok(output)
+    }
 
-        let mut output_file = BufWriter::new(map_err!(
-            output_file,
-            Error::Io,
-            format!("couldn't create output file: {}", output_path.display()),
-        )?);
+    fn write(&self, mut input: impl Read + Seek + 'static, output: &mut File) -> Result<(), Error> {
+        match self.opts.mode {
+            Mode::Convert => self.convert(input, &mut BufWriter::new(output)),
+            Mode::Copy => self.copy(&mut input, output),
+        }
+    }
 
-        info!("Writing DNG: \"{}\"", output_path.display());
+    fn convert(
+        &self,
+        input: impl Read + Seek + 'static,
+        output: &mut (impl Write + Seek + Send),
+    ) -> Result<(), Error> {
+        progress::suspend(|| info!("Writing DNG: \"{}\"", self.output_path.display()));
 
         let cvt_result = dng::convert::convert_raw_stream(
-            raw_file.file,
-            &mut output_file,
+            input,
+            output,
             self.input_path.to_string_lossy(),
-            &self.convert_opts,
+            &self.opts.convert,
         );
 
-        map_err!(cvt_result, Error::ImgOp, "couldn't convert image to DNG",)
+        map_err!(cvt_result, Error::ImgOp, "couldn't convert image to DNG")?;
+        map_err!(output.flush(), Error::Io, "couldn't write output file")
+    }
+
+    fn copy(&self, input: &mut impl Read, output: &mut File) -> Result<(), Error> {
+        progress::suspend(|| info!("Copying RAW: \"{}\"", self.output_path.display()));
+
+        map_err!(
+            io::copy(input, output),
+            Error::Io,
+            "couldn't copy input RAW file"
+        )?;
+
+        Ok(())
     }
 
-    pub async fn run(self) -> Result<(), Error> {
+    pub async fn run(self) -> Result<Option<Hashes>, Error> {
         tokio::task::spawn_blocking(|| self.run_blocking())
             .await
             .unwrap()
     }
 }
+
+/// Copies the output into `backup` instead of converting the source again
+fn mirror(output: &mut File, backup: &mut File, backup_path: &Path) -> Result<(), Error> {
+    progress::suspend(|| info!("Writing backup: \"{}\"", backup_path.display()));
+
+    map_err!(output.rewind(), Error::Io, "couldn't read back output file")?;
+    map_err!(
+        io::copy(output, backup),
+        Error::Io,
+        format!("couldn't write backup file: {}", backup_path.display())
+    )?;
+
+    let hash = |file: &mut File| {
+        file.rewind()?;
+        sha256(BufReader::new(file))
+    };
+
+    let expected = map_err!(hash(output), Error::Io, "couldn't read back output file")?;
+    let actual = map_err!(
+        hash(backup),
+        Error::Io,
+        format!("couldn't read back backup file: {}", backup_path.display())
+    )?;
+
+    if expected == actual {
+        Ok(())
+    } else {
+        Err(Error::VerifyFailed(format!(
+            "backup doesn't match the output: {}",
+            backup_path.display()
+        )))
+    }
+}
+
+fn verify_copy(input_path: &Path, copy: &mut File) -> Result<(), Error> {
+    map_err!(copy.rewind(), Error::Io, "couldn't read back copied file")?;
+
+    let expected = map_err!(
+        sha256_file(input_path),
+        Error::Io,
+        "couldn't hash input RAW file"
+    )?;
+    let actual = map_err!(
+        sha256(BufReader::new(copy)),
+        Error::Io,
+        "couldn't hash copied file"
+    )?;
+
+    if expected == actual {
+        Ok(())
+    } else {
+        Err(Error::VerifyFailed(
+            "copied file doesn't match the source".into(),
+        ))
+    }
+}
+
+#[cfg(test)]
+mod test_job {
+    use std::{
+        fs::{create_dir, read, write, OpenOptions},
+        io::Write as _,
+        path::Path,
+    };
+
+    use rawler::dng::convert::ConvertParams;
+    use tempfile::{tempdir, tempfile};
+
+    use super::{mirror, Error, Job, Mode, Options};
+    use crate::verify::Verify;
+
+    fn copy_job(input_path: &Path, output_path: &Path, backup_paths: &[&Path]) -> Job {
+        let opts = Options {
+            mode: Mode::Copy,
+            convert: ConvertParams::default(),
+            verify: Some(Verify::Decode),
+            quarantine_dir: None,
+            hash: false,
+        };
+
+        let backup_paths = backup_paths.iter().map(|&path| path.into()).collect();
+
+        Job::new(
+            input_path.into(),
+            output_path.into(),
+            backup_paths,
+            false,
+            opts,
+        )
+    }
+
+    #[test]
+    fn copies_byte_for_byte() {
+        let dir = tempdir().unwrap();
+        let input_path = dir.path().join("DSC01234.ARW");
+        let output_path = dir.path().join("out/2024_DSC01234.ARW");
+        write(&input_path, b"not really a RAW file").unwrap();
+
+        copy_job(&input_path, &output_path, &[])
+            .run_blocking()
+            .unwrap();
+
+        assert_eq!(read(output_path).unwrap(), b"not really a RAW file");
+    }
+
+    #[test]
+    fn writes_every_backup() {
+        let dir = tempdir().unwrap();
+        let input_path = dir.path().join("DSC01234.ARW");
+        let output_path = dir.path().join("laptop/DSC01234.ARW");
+        let backup_path = dir.path().join("ssd/DSC01234.ARW");
+        write(&input_path, b"not really a RAW file").unwrap();
+
+        copy_job(&input_path, &output_path, &[&backup_path])
+            .run_blocking()
+            .unwrap();
+
+        assert_eq!(read(output_path).unwrap(), b"not really a RAW file");
+        assert_eq!(read(backup_path).unwrap(), b"not really a RAW file");
+    }
+
+    #[test]
+    fn taken_backup_path_fails_before_writing() {
+        let dir = tempdir().unwrap();
+        let input_path = dir.path().join("DSC01234.ARW");
+        let output_path = dir.path().join("laptop/DSC01234.ARW");
+        let backup_path = dir.path().join("ssd/DSC01234.ARW");
+        write(&input_path, b"not really a RAW file").unwrap();
+        create_dir(dir.path().join("ssd")).unwrap();
+        write(&backup_path, b"older file").unwrap();
+
+        let result = copy_job(&input_path, &output_path, &[&backup_path]).run_blocking();
+
+        assert!(matches!(result, Err(Error::AlreadyExists(_))));
+        assert!(!output_path.exists());
+        assert_eq!(read(backup_path).unwrap(), b"older file");
+    }
+
+    #[test]
+    fn copy_extension_is_kept_verbatim() {
+        assert_eq!(Mode::Copy.extension(Path::new("a/DSC01234.ARW")), ".ARW");
+        assert_eq!(Mode::Copy.extension(Path::new("a/DSC01234")), "");
+        assert_eq!(Mode::Convert.extension(Path::new("a/DSC01234.ARW")), ".dng");
+    }
+
+    #[test]
+    fn rejects_backup_that_reads_back_different() {
+        let dir = tempdir().unwrap();
+        let backup_path = dir.path().join("DSC01234.ARW");
+        write(&backup_path, b"stale ").unwrap();
+
+        let mut output = tempfile().unwrap();
+        output.write_all(b"not really a RAW file").unwrap();
+
+        // appends to what's already there, so the backup ends up with more than the output
+        let mut backup = OpenOptions::new()
+            .read(true)
+            .append(true)
+            .open(&backup_path)
+            .unwrap();
+
+        assert!(matches!(
+            mirror(&mut output, &mut backup, &backup_path),
+            Err(Error::VerifyFailed(_))
+        ));
+    }
+}
@@ -0,0 +1,143 @@
+// Copyright (c) Carter J. Canedy <cartercanedy42@gmail.com>
+// rawbit is free software, distributable under the terms of the MIT license
+// See https://raw.githubusercontent.com/cartercanedy/rawbit/refs/heads/master/LICENSE.txt
+
+use std::{
+    fs::{create_dir_all, File, OpenOptions},
+    io::{self, Write as _},
+    path::{self, Path, PathBuf},
+    time::SystemTime,
+};
+
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+
+use crate::common::sync_dir;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Action {
+    Rename,
+    Convert,
+    Copy,
+    /// the source was deleted by `--move`, after it was written to the destination
+    DeleteSource,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Record {
+    pub action: Action,
+    pub source: PathBuf,
+    /// SHA-256 of the source, if it isn't the same file as the destination
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub source_hash: Option<String>,
+    pub destination: PathBuf,
+    pub hash: String,
+    /// whether writing the destination may have replaced a file that was already there
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub replaced: bool,
+}
+
+/// Records are synced to disk as they're appended, so that a killed run still leaves a journal of
+/// what it finished. Paths are made absolute, so that it can be replayed from any directory.
+#[derive(Debug)]
+pub struct Journal {
+    file: File,
+    path: PathBuf,
+}
+
+impl Journal {
+    /// Never replaces an existing journal
+    pub fn create(dir: &Path) -> io::Result<Self> {
+        create_dir_all(dir)?;
+
+        let created: DateTime<Utc> = SystemTime::now().into();
+        let path = dir.join(format!(
+            "rawbit-{}.journal",
+            created.format("%Y%m%dT%H%M%S%.3fZ")
+        ));
+
+        let file = OpenOptions::new()
+            .append(true)
+            .create_new(true)
+            .open(&path)?;
+        sync_dir(dir)?;
+
+        Ok(Self { file, path })
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    pub fn record(&mut self, record: &Record) -> io::Result<()> {
+        let record = Record {
+            source: path::absolute(&record.source)?,
+            destination: path::absolute(&record.destination)?,
+            ..record.clone()
+        };
+
+        let mut line = serde_json::to_string(&record)?;
+        line.push('\n');
+
+        self.file.write_all(line.as_bytes())?;
+        self.file.sync_data()
+    }
+}
+
+/// Lines that aren't records are reported by their line number
+pub fn parse(text: &str) -> Result<Vec<Record>, usize> {
+    text.lines()
+        .zip(1..)
+        .filter(|(line, _)| !line.trim().is_empty())
+        .map(|(line, n)| serde_json::from_str(line).map_err(|_| n))
+        .collect()
+}
+
+#[cfg(test)]
+mod test_journal {
+    use std::{fs::read_to_string, path::Path};
+
+    use tempfile::tempdir;
+
+    use super::{parse, Action, Journal, Record};
+
+    #[test]
+    fn appends_one_line_per_record_with_absolute_paths() {
+        let dir = tempdir().unwrap();
+        let mut journal = Journal::create(dir.path()).unwrap();
+
+        for name in ["a", "b"] {
+            journal
+                .record(&Record {
+                    action: Action::Rename,
+                    source: format!("raw/{name}.dng").into(),
+                    source_hash: None,
+                    destination: format!("/archive/{name}.dng").into(),
+                    hash: "ab".repeat(32),
+                    replaced: false,
+                })
+                .unwrap();
+        }
+
+        let text = read_to_string(journal.path()).unwrap();
+        let records = parse(&text).unwrap();
+
+        assert_eq!(records.len(), 2);
+        assert_eq!(records[0].action, Action::Rename);
+        assert!(records[0].source.is_absolute());
+        assert!(records[0].source.ends_with("raw/a.dng"));
+        assert_eq!(records[1].destination, Path::new("/archive/b.dng"));
+    }
+
+    #[test]
+    fn rejects_malformed_lines() {
+        let record = r#"{"action":"convert","source":"/a.CR3","source_hash":"00","destination":"/a.dng","hash":"11"}"#;
+
+        assert_eq!(parse(&format!("{record}\n\n{record}\n")).unwrap().len(), 2);
+        assert_eq!(
+            parse(&format!("{record}\n{{\"action\":\"paint\"}}\n")),
+            Err(2)
+        );
+    }
+}
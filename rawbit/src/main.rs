@@ -11,36 +11,77 @@
 )]
 #![allow(clippy::enum_glob_use, clippy::multiple_crate_versions)]
 
-use std::fmt::Display;
+use std::{
+    collections::HashMap,
+    fmt::Display,
+    fs::{canonicalize, metadata, read_to_string},
+    iter,
+    path::{self, Path, PathBuf},
+    process::ExitCode,
+    time::{Duration, Instant},
+};
 
-use clap::Parser as _;
 use futures::future::join_all;
-use parse::FilenameFormat;
-use rawler::dng::{convert::ConvertParams, CropMode, DngCompression};
+use parse::{FilenameFormat, MetadataKind};
 use rayon::{
-    iter::{IntoParallelRefIterator as _, ParallelIterator as _},
+    iter::{IntoParallelIterator as _, IntoParallelRefIterator as _, ParallelIterator as _},
     ThreadPoolBuilder,
 };
-use smlog::{debug, error, ignore, log::LevelFilter, warn, Log};
+use smlog::{debug, error, ignore, info, log::LevelFilter, warn, Log};
 use tokio::{fs, runtime::Builder};
 
 mod args;
 mod common;
+mod config;
+mod dry_run;
+mod events;
+#[cfg(test)]
+mod fixtures;
+mod inspect;
 mod job;
+mod journal;
+mod manifest;
+mod output;
 mod parse;
+mod plan;
+mod progress;
+mod removal;
+mod sanitize;
+mod summary;
+mod undo;
+mod verify;
 
-use args::{ImportConfig, IngestItem, LogConfig};
-use common::{map_err, AppError, RawbitResult};
-use job::Job;
+use args::{
+    Cli, Command, ImportConfig, IngestItem, InspectConfig, LogConfig, RenameConfig, UndoConfig,
+};
+use common::{map_err, sha256_file, AppError, RawbitResult};
+use dry_run::Rendered;
+use events::Event;
+use job::{Hashes, Job, Mode, Options};
+use journal::{Journal, Record};
+use manifest::{Entry, Manifest, Outcome};
+use plan::{Conflict, ImageSelection, Planned, Source};
+use removal::{Removal, Removals};
+use summary::Summary;
+use undo::Undone;
 
-fn main() -> Result<(), u32> {
-    let args = ImportConfig::parse();
-    let LogConfig {
+fn main() -> ExitCode {
+    let command = Cli::load();
+    if let Err(err) = command.validate() {
+        err.exit();
+    }
+    let &LogConfig {
         quiet,
         verbose: verbose_logs,
-    } = args.log_config;
+    } = command.log_config();
 
-    let filter: LevelFilter = if quiet {
+    command.output_format().init();
+
+    // log messages share stdout with events, which would break parsing them
+    let filter: LevelFilter = if events::enabled() {
+        ignore("rawler");
+        LevelFilter::Off
+    } else if quiet {
         ignore("rawler");
         LevelFilter::Error
     } else {
@@ -58,7 +99,7 @@ fn main() -> Result<(), u32> {
     Log::init(filter);
 
     ThreadPoolBuilder::new()
-        .num_threads(args.n_threads())
+        .num_threads(command.n_threads())
         .thread_name(|n| format!("rawbit-rayon-worker-{n}"))
         .build_global()
         .unwrap();
@@ -66,14 +107,22 @@ fn main() -> Result<(), u32> {
     let rt = Builder::new_multi_thread()
         .enable_all()
         .thread_name("rawbit-tokio-worker")
-        .worker_threads(args.n_threads())
+        .worker_threads(command.n_threads())
         .thread_stack_size(3 * 1024 * 1024)
         .build()
         .unwrap();
 
     let _rt_guard = rt.enter();
 
-    match rt.block_on(run(args)) {
+    let result = match command {
+        Command::Convert(args) => rt.block_on(run(*args)),
+        Command::Inspect(args) => inspect(args),
+        Command::Rename(args) => rename(args),
+        Command::Undo(args) => undo(&args),
+        Command::Verify(args) => verify_manifest(&args.manifest),
+    };
+
+    match result {
         Err(err) => {
             use AppError::*;
 
@@ -82,6 +131,8 @@ fn main() -> Result<(), u32> {
                 Io(s, ref e) => (s, Some(e), 2),
                 DirNotFound(s, ref e) => (format!("{s}: {}", e.display()), None, 3),
                 AlreadyExists(s, ref e) => (format!("{s}: {}", e.display()), None, 4),
+                VerifyFailed(s) => (s, None, 6),
+                JobsFailed(n) => (format!("{n} image(s) couldn't be processed"), None, 7),
                 Other(s, ref e) => (s, Some(e), 5),
             };
 
@@ -90,102 +141,937 @@ fn main() -> Result<(), u32> {
                 debug!("{cause}");
             }
 
-            Err(exit_code)
+            // the summary event already covers failed images
+            if exit_code != 7 {
+                events::emit(&Event::Fatal {
+                    message: &err_str,
+                    exit_code,
+                });
+            }
+
+            ExitCode::from(exit_code)
         }
 
-        _ => Ok(()),
+        _ => ExitCode::SUCCESS,
     }
 }
 
-async fn run(args: ImportConfig) -> RawbitResult<()> {
-    let n_threads = args.n_threads();
+fn report_job_error(input_path: &Path, output_path: Option<&Path>, err: &job::Error) {
+    progress::suspend(|| {
+        warn!(
+            "while processing \"{}\": {}",
+            input_path.display(),
+            err.message()
+        );
+        if let Some(dbg) = err.cause() {
+            debug!("Cause of last error:\n{dbg}");
+        }
+    });
 
-    let ImportConfig {
-        source,
-        output_dir,
-        fmt_str,
-        artist,
-        force,
-        embed,
-        recurse,
-        ..
-    } = args;
+    events::emit(&Event::failed(input_path, output_path, err));
+}
+
+fn report_conflict(conflict: &Conflict) {
+    match conflict {
+        Conflict::Skipped(planned, reason) | Conflict::Exists(planned, reason) => {
+            info!(
+                "skipping \"{}\": {reason}",
+                planned.source.input_path.display()
+            );
+
+            events::emit(&Event::Skipped {
+                input_path: planned.source.input_path.to_string_lossy(),
+                output_path: planned.output_path.to_string_lossy(),
+                reason,
+            });
+        }
+
+        Conflict::Failed(planned, err) => {
+            report_job_error(&planned.source.input_path, Some(&planned.output_path), err);
+        }
+    }
+}
 
-    let ingest = source.ingest(recurse)?.leak();
+fn report_scan(ingest: &[IngestItem], unsupported: &[PathBuf]) {
+    if !events::enabled() {
+        return;
+    }
+
+    let found = ingest.iter().map(|item| (&item.input_path, true));
+    let ignored = unsupported.iter().map(|path| (path, false));
+
+    for (path, supported) in found.chain(ignored) {
+        events::emit(&Event::Found {
+            path: path.to_string_lossy(),
+            supported,
+        });
+    }
+}
 
+fn read_sources(
+    ingest: Vec<IngestItem>,
+    images: ImageSelection,
+    mode: Mode,
+) -> (Vec<Source>, Vec<(PathBuf, job::Error)>) {
+    let read = ingest
+        .into_par_iter()
+        .map(|item| (item.input_path.clone(), Source::read(item, images, mode)))
+        .collect::<Vec<_>>();
+
+    let mut sources = vec![];
+    let mut unreadable = vec![];
+
+    for (input_path, result) in read {
+        match result {
+            Ok(read) => sources.extend(read),
+            Err(err) => {
+                report_job_error(&input_path, None, &err);
+                unreadable.push((input_path, err));
+            }
+        }
+    }
+
+    (sources, unreadable)
+}
+
+async fn prepare_output_dir(output_dir: &Path) -> RawbitResult<()> {
     if output_dir.exists() {
         if output_dir.is_dir() {
             Ok(())
         } else {
             Err(AppError::AlreadyExists(
                 "destination path exists and isn't a directory".into(),
-                (&output_dir).into(),
+                output_dir.into(),
             ))
         }
     } else {
         map_err!(
-            fs::create_dir_all(&output_dir).await,
+            fs::create_dir_all(output_dir).await,
             AppError::Io,
             "couldn't create destination directory"
         )
-    }?;
-
-    let fmt_str = fmt_str.map_or("", |s| s.leak() as &'static str);
-    let filename_format = Box::leak(Box::new(FilenameFormat::parse(fmt_str)?));
-
-    let opts = ConvertParams {
-        artist,
-        apply_scaling: false,
-        crop: CropMode::Best,
-        compression: DngCompression::Lossless,
-        embedded: embed,
-        index: 0,
-        preview: true,
-        thumbnail: true,
-        software: "rawbit".into(),
-        ..Default::default()
-    };
+    }
+}
+
+fn report_finished(planned: &Planned, elapsed: Duration) {
+    if !events::enabled() {
+        return;
+    }
 
-    for chunk in ingest.chunks(n_threads) {
+    let size = |path: &Path| metadata(path).ok().map(|md| md.len());
+
+    events::emit(&Event::Finished {
+        input_path: planned.source.input_path.to_string_lossy(),
+        output_path: planned.output_path.to_string_lossy(),
+        backup_paths: planned
+            .backup_paths
+            .iter()
+            .map(|path| path.to_string_lossy())
+            .collect(),
+        input_size: size(&planned.source.input_path),
+        output_size: size(&planned.output_path),
+        duration_ms: elapsed.as_millis(),
+    });
+}
+
+/// With `fail_fast`, no more jobs are started once one has failed, so fewer results than
+/// planned images may be returned.
+async fn run_jobs(
+    planned: &[Planned],
+    opts: &Options,
+    n_threads: usize,
+    fail_fast: bool,
+    mut journal: Option<&mut Journal>,
+) -> Vec<Result<Option<Hashes>, job::Error>> {
+    let mut results = Vec::with_capacity(planned.len());
+
+    for chunk in planned.chunks(n_threads) {
         let jobs = chunk
             .par_iter()
-            .cloned()
             .map(
-                |IngestItem {
-                     input_path,
-                     ref output_prefix,
+                |Planned {
+                     source,
+                     output_path,
+                     overwrite,
+                     backup_paths,
                  }| {
-                    let output_dir = output_dir.join(output_prefix);
-                    let job =
-                        Job::new(input_path, output_dir, filename_format, force, opts.clone());
+                    let mut opts = opts.clone();
+                    opts.convert.index = source.image.index;
+
+                    let job = Job::new(
+                        source.input_path.clone(),
+                        output_path.clone(),
+                        backup_paths.clone(),
+                        *overwrite,
+                        opts,
+                    );
+
+                    async move {
+                        events::emit(&Event::Started {
+                            input_path: source.input_path.to_string_lossy(),
+                            output_path: output_path.to_string_lossy(),
+                            image_index: source.image.index,
+                        });
+
+                        let started = Instant::now();
+                        let result = job.run().await;
+
+                        if progress::active() {
+                            let size = |path: &Path| metadata(path).map_or(0, |md| md.len());
+                            let copies = 1 + backup_paths.len() as u64;
+
+                            progress::advance(
+                                size(&source.input_path),
+                                result.as_ref().map_or(0, |_| size(output_path) * copies),
+                            );
+                        }
 
-                    job.run()
+                        (result, started.elapsed())
+                    }
                 },
             )
             .collect::<Vec<_>>();
 
-        join_all(jobs)
-            .await
-            .into_iter()
-            .zip(chunk.iter().map(|item| item.input_path.clone()))
-            .for_each(|(result, input_path)| {
-                if let Err(cvt_err) = result {
-                    use job::Error::*;
-
-                    let (err_str, cause): (&str, Option<&dyn Display>) = match cvt_err {
-                        AlreadyExists(ref err_str) => (err_str, None),
-                        Io(ref err_str, ref cause) => (err_str, Some(cause)),
-                        ImgOp(ref err_str, ref cause) => (err_str, Some(cause)),
-                        Other(ref err_str, ref cause) => (err_str, Some(cause)),
-                    };
-
-                    warn!("while processing \"{}\": {err_str}", input_path.display());
-                    if let Some(dbg) = cause {
-                        debug!("Cause of last error:\n{dbg}");
-                    }
+        for ((mut result, elapsed), planned) in join_all(jobs).await.into_iter().zip(chunk) {
+            if let (Some(journal), Ok(Some(hashes))) = (journal.as_deref_mut(), &result) {
+                if let Err(err) = record_outputs(journal, planned, opts.mode, hashes) {
+                    result = Err(err);
                 }
-            });
+            }
+
+            match result {
+                Ok(_) => report_finished(planned, elapsed),
+                Err(ref cvt_err) => report_job_error(
+                    &planned.source.input_path,
+                    Some(&planned.output_path),
+                    cvt_err,
+                ),
+            }
+
+            results.push(result);
+        }
+
+        if fail_fast && results.iter().any(Result::is_err) {
+            break;
+        }
+    }
+
+    results
+}
+
+fn create_journal(dir: &Path) -> RawbitResult<Journal> {
+    map_err!(
+        Journal::create(dir),
+        AppError::Io,
+        format!("couldn't create a journal in {}", dir.display())
+    )
+}
+
+fn record_outputs(
+    journal: &mut Journal,
+    planned: &Planned,
+    mode: Mode,
+    hashes: &Hashes,
+) -> Result<(), job::Error> {
+    let action = match mode {
+        Mode::Convert => journal::Action::Convert,
+        Mode::Copy => journal::Action::Copy,
+    };
+
+    for output_path in planned.paths() {
+        let record = Record {
+            action,
+            source: planned.source.input_path.clone(),
+            source_hash: Some(hashes.source.clone()),
+            destination: output_path.clone(),
+            hash: hashes.output.clone(),
+            replaced: planned.overwrite,
+        };
+
+        map_err!(
+            journal.record(&record),
+            job::Error::Io,
+            "written, but couldn't record it in the journal"
+        )?;
+    }
+
+    Ok(())
+}
+
+fn write_manifest(
+    planned: &[Planned],
+    results: &[Result<Option<Hashes>, job::Error>],
+    output_dir: &Path,
+    backup_dirs: &[PathBuf],
+    mhl: bool,
+) -> RawbitResult<()> {
+    let entries = planned
+        .iter()
+        .zip(results)
+        .filter_map(|(planned, result)| {
+            let Ok(Some(hashes)) = result else {
+                return None;
+            };
+
+            let input_path = &planned.source.input_path;
+            let output_path = &planned.output_path;
+
+            Some(Entry {
+                source_path: canonicalize(input_path).unwrap_or_else(|_| input_path.clone()),
+                source_hash: hashes.source.clone(),
+                output_path: output_path
+                    .strip_prefix(output_dir)
+                    .unwrap_or(output_path)
+                    .to_path_buf(),
+                output_hash: hashes.output.clone(),
+                output_size: metadata(output_path).map_or(0, |md| md.len()),
+            })
+        })
+        .collect();
+
+    let manifest = Manifest::new(entries);
+
+    for dir in iter::once(output_dir).chain(backup_dirs.iter().map(PathBuf::as_path)) {
+        let path = map_err!(
+            manifest.write_to(dir, mhl),
+            AppError::Io,
+            format!("couldn't write manifest to {}", dir.display())
+        )?;
+
+        info!("Wrote manifest: \"{}\"", path.display());
     }
 
     Ok(())
 }
+
+fn verify_manifest(path: &Path) -> RawbitResult<()> {
+    let text = map_err!(read_to_string(path), AppError::Io, "couldn't read manifest")?;
+
+    let listed = manifest::parse(&text, output::parent_dir(path)).map_err(|line| {
+        AppError::VerifyFailed(format!("line {line} of the manifest isn't a checksum"))
+    })?;
+
+    let total = listed.len();
+    let mut failed = 0;
+
+    for (listed, outcome) in manifest::check(listed) {
+        let output_path = listed.path.display();
+
+        match outcome {
+            Outcome::Ok => info!("OK: \"{output_path}\""),
+            Outcome::Mismatch => {
+                failed += 1;
+                error!("checksum mismatch: \"{output_path}\"");
+            }
+            Outcome::Unreadable(err) => {
+                failed += 1;
+                error!("couldn't read \"{output_path}\"");
+                debug!("Cause of last error:\n{err}");
+            }
+        }
+
+        if let Some(source) = listed.source {
+            debug!("written from \"{source}\"");
+        }
+    }
+
+    if failed == 0 {
+        info!("all {total} outputs match the manifest");
+        Ok(())
+    } else {
+        Err(AppError::VerifyFailed(format!(
+            "{failed} of {total} outputs don't match the manifest"
+        )))
+    }
+}
+
+/// Returns the sources that were deleted.
+fn remove_sources(
+    mut removals: Removals,
+    planned: &[Planned],
+    results: &[Result<Option<Hashes>, job::Error>],
+    conflicts: &[Conflict],
+    unreadable: &[(PathBuf, job::Error)],
+) -> Vec<PathBuf> {
+    for (input_path, err) in unreadable {
+        removals.blocked(input_path, err.message().into());
+    }
+
+    for conflict in conflicts {
+        let (planned, reason) = match conflict {
+            Conflict::Skipped(planned, reason) | Conflict::Exists(planned, reason) => {
+                (planned, reason.as_str())
+            }
+            Conflict::Failed(planned, err) => (planned, err.message()),
+        };
+
+        removals.blocked(&planned.source.input_path, reason.into());
+    }
+
+    removals.finished(planned, results);
+
+    let mut deleted = vec![];
+
+    for (input_path, removal) in removals.apply() {
+        match removal {
+            Removal::Deleted => {
+                info!("deleted source \"{}\"", input_path.display());
+                deleted.push(input_path);
+            }
+            Removal::Kept(reason) => warn!("kept source \"{}\": {reason}", input_path.display()),
+            Removal::Failed(err) => {
+                warn!("couldn't delete source \"{}\"", input_path.display());
+                debug!("Cause of last error:\n{err}");
+            }
+        }
+    }
+
+    deleted
+}
+
+/// A deleted source is recorded with its first output, which `rawbit undo` can restore it from
+fn record_deleted(
+    journal: &mut Journal,
+    deleted: &[PathBuf],
+    planned: &[Planned],
+    results: &[Result<Option<Hashes>, job::Error>],
+) {
+    for input_path in deleted {
+        let written = planned
+            .iter()
+            .zip(results)
+            .find_map(|(planned, result)| match result {
+                Ok(Some(hashes)) if planned.source.input_path == *input_path => {
+                    Some((planned, hashes))
+                }
+                _ => None,
+            });
+
+        let Some((planned, hashes)) = written else {
+            continue;
+        };
+
+        let record = Record {
+            action: journal::Action::DeleteSource,
+            source: input_path.clone(),
+            source_hash: Some(hashes.source.clone()),
+            destination: planned.output_path.clone(),
+            hash: hashes.output.clone(),
+            replaced: false,
+        };
+
+        if let Err(err) = journal.record(&record) {
+            warn!(
+                "couldn't record the deletion of \"{}\" in the journal",
+                input_path.display()
+            );
+            debug!("Cause of last error:\n{err}");
+        }
+    }
+}
+
+fn inspect(args: InspectConfig) -> RawbitResult<()> {
+    let (ingest, unsupported) = args.source.ingest(args.recurse)?;
+    report_scan(&ingest, &unsupported);
+
+    let (mut sources, unreadable) = read_sources(ingest, ImageSelection::All, Mode::Convert);
+    sources.sort_by(|a, b| (&a.input_path, a.image.index).cmp(&(&b.input_path, b.image.index)));
+
+    inspect::report(&sources);
+
+    if unreadable.is_empty() {
+        Ok(())
+    } else {
+        Err(AppError::JobsFailed(unreadable.len()))
+    }
+}
+
+fn rename(args: RenameConfig) -> RawbitResult<()> {
+    let journal_dir = args.journal_dir();
+    let quiet = args.log_config.quiet;
+
+    let RenameConfig {
+        source,
+        output_dir,
+        fmt_str,
+        sanitize,
+        on_collision,
+        recurse,
+        dry_run,
+        ..
+    } = args;
+
+    let (mut ingest, unsupported) = source.ingest(recurse)?;
+    report_scan(&ingest, &unsupported);
+
+    let filename_format = FilenameFormat::parse(&fmt_str)?.with_profile(sanitize);
+
+    // without an output dir, every file stays in the directory it's in
+    if output_dir.is_none() {
+        for item in &mut ingest {
+            item.output_prefix = item
+                .input_path
+                .parent()
+                .map(Path::to_path_buf)
+                .unwrap_or_default();
+        }
+    }
+
+    let output_dir = output_dir.unwrap_or_default();
+    let (sources, unreadable) = read_sources(ingest, ImageSelection::Index(0), Mode::Convert);
+
+    let (planned, unchanged): (Vec<_>, Vec<_>) =
+        plan::plan(sources, &output_dir, &filename_format, Mode::Copy)
+            .into_iter()
+            .partition(|planned| planned.output_path != planned.source.input_path);
+
+    let rendered = dry_run.then(|| Rendered::new(&planned));
+    let (planned, mut conflicts) = plan::resolve_renames(&planned, on_collision);
+
+    conflicts.extend(
+        unchanged
+            .into_iter()
+            .map(|planned| Conflict::Skipped(planned, "already named after the format".into())),
+    );
+
+    if let Some(rendered) = rendered {
+        let rows = dry_run::rows(
+            &planned,
+            &conflicts,
+            &unreadable,
+            &rendered,
+            &filename_format,
+        );
+        dry_run::report(&rows);
+        return Ok(());
+    }
+
+    conflicts.iter().for_each(report_conflict);
+
+    let mut summary = Summary::default();
+    summary.unsupported = unsupported.len();
+
+    for (_, err) in &unreadable {
+        summary.fail(err);
+    }
+
+    for conflict in &conflicts {
+        summary.conflict(conflict);
+    }
+
+    if !planned.is_empty() {
+        let mut journal = create_journal(&journal_dir)?;
+
+        for result in rename_files(&planned, &mut journal) {
+            summary.finished(&result);
+        }
+
+        info!("Wrote journal: \"{}\"", journal.path().display());
+    }
+
+    if events::enabled() {
+        events::emit(&Event::summary(&summary));
+    } else if !quiet {
+        print!("\n{summary}");
+    }
+
+    match summary.failures() {
+        0 => Ok(()),
+        n => Err(AppError::JobsFailed(n)),
+    }
+}
+
+/// The sources are hashed up front, in parallel, so that the journal can tell later whether a
+/// file has changed since it was renamed. Moving a file aside to break a cycle is journaled like
+/// any other rename, so that undo can retrace it.
+fn rename_files(planned: &[Planned], journal: &mut Journal) -> Vec<Result<(), job::Error>> {
+    let mut hashes = planned
+        .par_iter()
+        .map(|planned| {
+            map_err!(
+                sha256_file(&planned.source.input_path),
+                job::Error::Io,
+                "couldn't hash source file"
+            )
+        })
+        .collect::<Vec<_>>();
+
+    for (planned, hash) in planned.iter().zip(&hashes) {
+        if let Err(err) = hash {
+            report_job_error(&planned.source.input_path, Some(&planned.output_path), err);
+        }
+    }
+
+    let mut aside = HashMap::new();
+
+    for (index, set_aside) in plan::rename_order(planned) {
+        let Ok(hash) = &hashes[index] else {
+            continue;
+        };
+
+        let started = Instant::now();
+        let planned = &planned[index];
+        let input_path = &planned.source.input_path;
+        let from = aside.get(&index).unwrap_or(input_path);
+
+        let to = if set_aside {
+            let filename = input_path.file_name().unwrap_or_default().to_string_lossy();
+            input_path.with_file_name(format!(".rawbit-{filename}.tmp"))
+        } else {
+            planned.output_path.clone()
+        };
+
+        let result = output::move_file(from, &to).and_then(|()| {
+            let record = Record {
+                action: journal::Action::Rename,
+                source: from.clone(),
+                source_hash: None,
+                destination: to.clone(),
+                hash: hash.clone(),
+                replaced: false,
+            };
+
+            map_err!(
+                journal.record(&record),
+                job::Error::Io,
+                "renamed, but couldn't record it in the journal"
+            )
+        });
+
+        match result {
+            Ok(()) if set_aside => {
+                debug!("Moved \"{}\" aside to \"{}\"", from.display(), to.display());
+                aside.insert(index, to);
+            }
+            Ok(()) => {
+                info!(
+                    "Renamed \"{}\" to \"{}\"",
+                    input_path.display(),
+                    to.display()
+                );
+                report_finished(planned, started.elapsed());
+            }
+            Err(err) => {
+                report_job_error(input_path, Some(&planned.output_path), &err);
+                hashes[index] = Err(err);
+            }
+        }
+    }
+
+    hashes.into_iter().map(|hash| hash.map(drop)).collect()
+}
+
+fn undo(args: &UndoConfig) -> RawbitResult<()> {
+    let text = map_err!(
+        read_to_string(&args.journal),
+        AppError::Io,
+        "couldn't read journal"
+    )?;
+
+    let records = journal::parse(&text).map_err(|line| {
+        AppError::VerifyFailed(format!("line {line} of the journal isn't a record"))
+    })?;
+
+    let root = map_err!(
+        path::absolute(output::parent_dir(&args.journal)),
+        AppError::Io,
+        "couldn't resolve the journal's directory"
+    )?;
+
+    let (mut reverted, mut skipped, mut failed) = (0, 0, 0);
+
+    for (record, result) in undo::undo(&records, &root) {
+        let (source, destination) = (record.source.display(), record.destination.display());
+
+        match result {
+            Ok(Undone::Reverted) => {
+                reverted += 1;
+
+                if record.action == journal::Action::Rename {
+                    info!("Renamed \"{destination}\" back to \"{source}\"");
+                } else {
+                    info!("Removed \"{destination}\"");
+                }
+            }
+            Ok(Undone::Restored) => {
+                reverted += 1;
+                info!("Restored \"{source}\" from \"{destination}\"");
+            }
+            Ok(Undone::Skipped(reason)) => {
+                skipped += 1;
+                debug!("skipped \"{destination}\": {reason}");
+            }
+            Err(err) => {
+                failed += 1;
+                report_job_error(&record.destination, None, &err);
+            }
+        }
+    }
+
+    if !args.log_config.quiet {
+        println!("\n{reverted} reverted, {skipped} skipped, {failed} failed");
+    }
+
+    match failed {
+        0 => Ok(()),
+        n => Err(AppError::JobsFailed(n)),
+    }
+}
+
+async fn run(args: ImportConfig) -> RawbitResult<()> {
+    let n_threads = args.n_threads();
+    let images = args.image_selection();
+    let on_collision = args.collision_strategy();
+    let opts = args.job_options();
+    let (move_sources, manifest, mhl) = (args.move_sources, args.manifest, args.mhl);
+    let journal = args.journal;
+    let (fail_fast, quiet) = (args.fail_fast, args.log_config.quiet);
+    let dry_run = args.dry_run;
+
+    let ImportConfig {
+        source,
+        output_dir,
+        backup_dirs,
+        fmt_str,
+        recurse,
+        sanitize,
+        ..
+    } = args;
+
+    let output_dir = output_dir.expect("--out-dir is required without a subcommand");
+    let (ingest, unsupported) = source.ingest(recurse)?;
+    report_scan(&ingest, &unsupported);
+
+    if !dry_run {
+        for dir in iter::once(&output_dir).chain(&backup_dirs) {
+            prepare_output_dir(dir).await?;
+        }
+    }
+
+    let filename_format =
+        FilenameFormat::parse(fmt_str.as_deref().unwrap_or_default())?.with_profile(sanitize);
+
+    if images == ImageSelection::All && !filename_format.expands(MetadataKind::ImageIndex) {
+        warn!("the format doesn't contain {{image.index}}, images from the same RAW file will collide");
+    }
+
+    let removals = move_sources.then(|| Removals::new(&ingest));
+    let (sources, unreadable) = read_sources(ingest, images, opts.mode);
+
+    let mut planned = plan::plan(sources, &output_dir, &filename_format, opts.mode);
+    plan::add_backups(&mut planned, &output_dir, &backup_dirs);
+
+    let rendered = dry_run.then(|| Rendered::new(&planned));
+    let (planned, conflicts) = plan::resolve_collisions(planned, on_collision);
+
+    if let Some(rendered) = rendered {
+        let rows = dry_run::rows(
+            &planned,
+            &conflicts,
+            &unreadable,
+            &rendered,
+            &filename_format,
+        );
+        dry_run::report(&rows);
+        return Ok(());
+    }
+
+    conflicts.iter().for_each(report_conflict);
+
+    let mut summary = Summary::default();
+    summary.unsupported = unsupported.len();
+
+    for (_, err) in &unreadable {
+        summary.fail(err);
+    }
+
+    for conflict in &conflicts {
+        summary.conflict(conflict);
+    }
+
+    let mut journal = (journal && !planned.is_empty())
+        .then(|| create_journal(&output_dir))
+        .transpose()?;
+
+    let results = if fail_fast && summary.failures() > 0 {
+        vec![]
+    } else {
+        if progress::wanted(quiet) {
+            progress::start(planned.len());
+        }
+
+        let results = run_jobs(&planned, &opts, n_threads, fail_fast, journal.as_mut()).await;
+        progress::finish();
+        results
+    };
+
+    for result in &results {
+        summary.finished(result);
+    }
+    summary.aborted = planned.len() - results.len();
+
+    // before sources are deleted, so that a failed manifest keeps them around
+    if manifest {
+        write_manifest(&planned, &results, &output_dir, &backup_dirs, mhl)?;
+    }
+
+    if let Some(removals) = removals {
+        let deleted = remove_sources(removals, &planned, &results, &conflicts, &unreadable);
+
+        if let Some(journal) = &mut journal {
+            record_deleted(journal, &deleted, &planned, &results);
+        }
+    }
+
+    if let Some(journal) = &journal {
+        info!("Wrote journal: \"{}\"", journal.path().display());
+    }
+
+    if events::enabled() {
+        events::emit(&Event::summary(&summary));
+    } else if !quiet {
+        print!("{summary}");
+    }
+
+    match summary.failures() {
+        0 => Ok(()),
+        n => Err(AppError::JobsFailed(n)),
+    }
+}
+
+#[cfg(test)]
+mod test_main {
+    use std::{
+        ffi::OsStr,
+        fs::{create_dir, read, read_dir, read_to_string, write, File},
+    };
+
+    use clap::Parser as _;
+    use rawler::exif::Exif;
+    use tempfile::tempdir;
+    use tokio::runtime::Builder;
+
+    use super::{create_journal, rename, rename_files, run};
+    use crate::{
+        args::{Cli, Command},
+        common::RawbitResult,
+        fixtures::{self, write_dng},
+        journal,
+        plan::Planned,
+        undo,
+    };
+
+    fn import(args: &[&OsStr]) -> RawbitResult<()> {
+        let cli = Cli::try_parse_from(["rawbit".as_ref(), "-q".as_ref()].iter().chain(args));
+        let Command::Convert(config) = cli.unwrap().into_command() else {
+            panic!("expected convert");
+        };
+
+        Builder::new_current_thread()
+            .enable_all()
+            .build()
+            .unwrap()
+            .block_on(run(*config))
+    }
+
+    #[test]
+    fn importing_again_skips_existing_outputs() {
+        let dir = tempdir().unwrap();
+        let (raw, out) = (dir.path().join("raw"), dir.path().join("out"));
+        let input_path = raw.join("a.dng");
+        create_dir(&raw).unwrap();
+        write_dng(&mut File::create(&input_path).unwrap(), "RGGB", |i| {
+            i % 4096
+        });
+
+        let args = ["-o".as_ref(), out.as_os_str(), input_path.as_os_str()];
+        import(&args).unwrap();
+        import(&args).unwrap();
+
+        let outputs = read_dir(&out)
+            .unwrap()
+            .filter(|entry| entry.as_ref().unwrap().path().extension() == Some("dng".as_ref()))
+            .count();
+        assert_eq!(outputs, 1);
+    }
+
+    #[test]
+    fn copies_files_that_cant_be_decoded() {
+        let dir = tempdir().unwrap();
+        let (raw, out) = (dir.path().join("raw"), dir.path().join("out"));
+        let input_path = raw.join("a.ORF");
+        create_dir(&raw).unwrap();
+        write(&input_path, b"not a RAW file").unwrap();
+
+        import(&[
+            "--copy".as_ref(),
+            "-o".as_ref(),
+            out.as_os_str(),
+            input_path.as_os_str(),
+        ])
+        .unwrap();
+
+        assert_eq!(read(out.join("a.ORF")).unwrap(), b"not a RAW file");
+    }
+
+    #[test]
+    fn renames_a_chain_of_files() {
+        let dir = tempdir().unwrap();
+        let (a, b) = (dir.path().join("a.dng"), dir.path().join("x_a.dng"));
+        write_dng(&mut File::create(&a).unwrap(), "RGGB", |i| i % 4096);
+        write_dng(&mut File::create(&b).unwrap(), "RGGB", |i| i % 2048);
+        let (a_contents, b_contents) = (read(&a).unwrap(), read(&b).unwrap());
+
+        // a.dng takes the place of x_a.dng, which is renamed to x_x_a.dng
+        let cli = Cli::try_parse_from([
+            "rawbit".as_ref(),
+            "rename".as_ref(),
+            "-q".as_ref(),
+            "-F".as_ref(),
+            "x_{image.original_filename}".as_ref(),
+            a.as_os_str(),
+            b.as_os_str(),
+        ]);
+        let Command::Rename(args) = cli.unwrap().into_command() else {
+            panic!("expected rename");
+        };
+
+        rename(args).unwrap();
+
+        assert!(!a.exists());
+        assert_eq!(read(&b).unwrap(), a_contents);
+        assert_eq!(read(dir.path().join("x_x_a.dng")).unwrap(), b_contents);
+    }
+
+    #[test]
+    fn renames_a_cycle_through_a_temporary_name() {
+        let dir = tempdir().unwrap();
+        let (a, b) = (dir.path().join("a.RAF"), dir.path().join("b.RAF"));
+        write(&a, b"a").unwrap();
+        write(&b, b"b").unwrap();
+
+        let planned = [(&a, &b), (&b, &a)].map(|(from, to)| Planned {
+            source: fixtures::source(from, fixtures::metadata("", "", Exif::default())),
+            output_path: to.clone(),
+            overwrite: false,
+            backup_paths: vec![],
+        });
+
+        let mut journal = create_journal(dir.path()).unwrap();
+        assert!(rename_files(&planned, &mut journal)
+            .iter()
+            .all(Result::is_ok));
+        assert_eq!(
+            (read(&a).unwrap(), read(&b).unwrap()),
+            (b"b".into(), b"a".into())
+        );
+
+        let records = journal::parse(&read_to_string(journal.path()).unwrap()).unwrap();
+        let undone = undo::undo(&records, dir.path());
+        assert!(undone.iter().all(|(_, result)| result.is_ok()));
+        assert_eq!(
+            (read(&a).unwrap(), read(&b).unwrap()),
+            (b"a".into(), b"b".into())
+        );
+    }
+}
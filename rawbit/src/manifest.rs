@@ -0,0 +1,355 @@
+// Copyright (c) Carter J. Canedy <cartercanedy42@gmail.com>
+// rawbit is free software, distributable under the terms of the MIT license
+// See https://raw.githubusercontent.com/cartercanedy/rawbit/refs/heads/master/LICENSE.txt
+
+use std::{
+    fmt::Write as _,
+    fs::OpenOptions,
+    io::{self, Write as _},
+    path::{Component, Path, PathBuf},
+    time::SystemTime,
+};
+
+use chrono::{DateTime, SecondsFormat, Utc};
+use rayon::iter::{IntoParallelIterator as _, ParallelIterator as _};
+
+use crate::common::{sha256_file, sync_dir};
+
+/// Marks comment lines that record the source of the output on the following line
+const SOURCE_PREFIX: &str = "# source ";
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Entry {
+    pub source_path: PathBuf,
+    pub source_hash: String,
+    /// relative to the output directory
+    pub output_path: PathBuf,
+    pub output_hash: String,
+    pub output_size: u64,
+}
+
+/// The `.sha256` file is understood by `sha256sum -c`, which skips the comment lines that record
+/// each output's source. Paths are relative to the directory the manifest is written to.
+#[derive(Debug)]
+pub struct Manifest {
+    created: DateTime<Utc>,
+    entries: Vec<Entry>,
+}
+
+impl Manifest {
+    pub fn new(entries: Vec<Entry>) -> Self {
+        Self {
+            created: SystemTime::now().into(),
+            entries,
+        }
+    }
+
+    /// Unique to the millisecond the run finished
+    pub fn file_stem(&self) -> String {
+        format!("rawbit-{}", self.created.format("%Y%m%dT%H%M%S%.3fZ"))
+    }
+
+    /// Existing manifests are never replaced
+    pub fn write_to(&self, dir: &Path, mhl: bool) -> io::Result<PathBuf> {
+        let path = dir.join(format!("{}.sha256", self.file_stem()));
+        write_new(&path, &self.sha256sum())?;
+
+        if mhl {
+            write_new(&path.with_extension("mhl"), &self.mhl())?;
+        }
+
+        sync_dir(dir)?;
+        Ok(path)
+    }
+
+    fn sha256sum(&self) -> String {
+        let mut out = format!(
+            "# rawbit {} manifest, created {}\n",
+            env!("CARGO_PKG_VERSION"),
+            self.created.to_rfc3339_opts(SecondsFormat::Secs, false)
+        );
+
+        for entry in &self.entries {
+            let _ = writeln!(
+                out,
+                "{SOURCE_PREFIX}{}  {}",
+                entry.source_hash,
+                escape(&entry.source_path.to_string_lossy())
+            );
+
+            let _ = writeln!(
+                out,
+                "{}",
+                sha256sum_line(&entry.output_hash, &portable(&entry.output_path))
+            );
+        }
+
+        out
+    }
+
+    fn mhl(&self) -> String {
+        let created = self.created.to_rfc3339_opts(SecondsFormat::Secs, false);
+
+        let mut out = format!(
+            "<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n\
+             <hashlist version=\"2.0\" xmlns=\"urn:ASC:MHL:v2.0\">\n  \
+               <creatorinfo>\n    \
+                 <creationdate>{created}</creationdate>\n    \
+                 <tool version=\"{}\">rawbit</tool>\n  \
+               </creatorinfo>\n  \
+               <processinfo>\n    \
+                 <process>transfer</process>\n  \
+               </processinfo>\n  \
+               <hashes>\n",
+            env!("CARGO_PKG_VERSION"),
+        );
+
+        for entry in &self.entries {
+            let _ = write!(
+                out,
+                "    <hash>\n      \
+                       <path size=\"{}\">{}</path>\n      \
+                       <sha256 action=\"original\" hashdate=\"{created}\">{}</sha256>\n    \
+                     </hash>\n",
+                entry.output_size,
+                xml_escape(&portable(&entry.output_path)),
+                entry.output_hash,
+            );
+        }
+
+        out.push_str("  </hashes>\n</hashlist>\n");
+        out
+    }
+}
+
+fn write_new(path: &Path, contents: &str) -> io::Result<()> {
+    let mut file = OpenOptions::new().write(true).create_new(true).open(path)?;
+    file.write_all(contents.as_bytes())?;
+    file.sync_all()
+}
+
+/// `path` with `/` separators, so that manifests written on Windows can be checked elsewhere
+fn portable(path: &Path) -> String {
+    path.components()
+        .filter_map(|component| match component {
+            Component::Normal(name) => Some(name.to_string_lossy()),
+            _ => None,
+        })
+        .collect::<Vec<_>>()
+        .join("/")
+}
+
+/// Escapes backslashes and newlines the way `sha256sum` does
+fn escape(name: &str) -> String {
+    name.replace('\\', "\\\\").replace('\n', "\\n")
+}
+
+fn sha256sum_line(hash: &str, name: &str) -> String {
+    let escaped = escape(name);
+
+    if escaped == name {
+        format!("{hash}  {name}")
+    } else {
+        format!("\\{hash}  {escaped}")
+    }
+}
+
+fn xml_escape(s: &str) -> String {
+    s.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+        .replace('\'', "&apos;")
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub struct Listed {
+    pub path: PathBuf,
+    pub hash: String,
+    pub source: Option<String>,
+}
+
+#[derive(Debug)]
+pub enum Outcome {
+    Ok,
+    Mismatch,
+    Unreadable(io::Error),
+}
+
+/// Lines that aren't checksums are reported by their line number
+pub fn parse(text: &str, dir: &Path) -> Result<Vec<Listed>, usize> {
+    let mut listed = vec![];
+    let mut source = None;
+
+    for (line, n) in text.lines().zip(1..) {
+        if let Some(rest) = line.strip_prefix(SOURCE_PREFIX) {
+            source = rest.split_once("  ").map(|(_, path)| unescape(path));
+            continue;
+        }
+
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+
+        let (escaped, line) = line
+            .strip_prefix('\\')
+            .map_or((false, line), |line| (true, line));
+
+        let (hash, name) = line
+            .split_once("  ")
+            .or_else(|| line.split_once(" *"))
+            .ok_or(n)?;
+
+        if hash.len() != 64 || !hash.bytes().all(|b| b.is_ascii_hexdigit()) {
+            return Err(n);
+        }
+
+        let name = if escaped {
+            unescape(name)
+        } else {
+            name.to_string()
+        };
+
+        listed.push(Listed {
+            path: dir.join(name),
+            hash: hash.to_ascii_lowercase(),
+            source: source.take(),
+        });
+    }
+
+    Ok(listed)
+}
+
+fn unescape(name: &str) -> String {
+    let mut out = String::with_capacity(name.len());
+    let mut chars = name.chars();
+
+    while let Some(c) = chars.next() {
+        match (c, chars.clone().next()) {
+            ('\\', Some('n')) => {
+                out.push('\n');
+                chars.next();
+            }
+            ('\\', Some('\\')) => {
+                out.push('\\');
+                chars.next();
+            }
+            _ => out.push(c),
+        }
+    }
+
+    out
+}
+
+pub fn check(listed: Vec<Listed>) -> Vec<(Listed, Outcome)> {
+    listed
+        .into_par_iter()
+        .map(|listed| {
+            let outcome = match sha256_file(&listed.path) {
+                Ok(hash) if hash == listed.hash => Outcome::Ok,
+                Ok(_) => Outcome::Mismatch,
+                Err(err) => Outcome::Unreadable(err),
+            };
+
+            (listed, outcome)
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod test_manifest {
+    use std::{
+        fs::{read_to_string, write},
+        path::Path,
+        time::Duration,
+    };
+
+    use tempfile::tempdir;
+
+    use super::{check, parse, Entry, Manifest, Outcome};
+    use crate::common::sha256;
+
+    fn entry(output_path: &str, contents: &[u8]) -> Entry {
+        Entry {
+            source_path: "/media/card/DCIM/IMG_0001.CR3".into(),
+            source_hash: "ab".repeat(32),
+            output_path: output_path.into(),
+            output_hash: sha256(contents).unwrap(),
+            output_size: contents.len() as u64,
+        }
+    }
+
+    #[test]
+    fn round_trips_through_sha256sum_format() {
+        let manifest = Manifest::new(vec![
+            entry("2024/IMG_0001.dng", b"one"),
+            entry("odd\\name\n.dng", b"two"),
+        ]);
+
+        let text = manifest.sha256sum();
+        let listed = parse(&text, Path::new("/dst")).unwrap();
+
+        assert!(text.contains(&format!(
+            "{}  2024/IMG_0001.dng\n",
+            sha256(&b"one"[..]).unwrap()
+        )));
+        assert_eq!(listed.len(), 2);
+        assert_eq!(listed[0].path, Path::new("/dst/2024/IMG_0001.dng"));
+        assert_eq!(listed[1].path, Path::new("/dst/odd\\name\n.dng"));
+        assert_eq!(
+            listed[0].source.as_deref(),
+            Some("/media/card/DCIM/IMG_0001.CR3")
+        );
+    }
+
+    #[test]
+    fn rejects_malformed_lines() {
+        assert_eq!(parse("# comment\nnot a checksum\n", Path::new(".")), Err(2));
+        assert_eq!(parse("abcd  short.dng\n", Path::new(".")), Err(1));
+    }
+
+    #[test]
+    fn detects_changed_and_missing_outputs() {
+        let dir = tempdir().unwrap();
+        let manifest = Manifest::new(vec![
+            entry("A.dng", b"one"),
+            entry("B.dng", b"two"),
+            entry("C.dng", b"three"),
+        ]);
+
+        write(dir.path().join("A.dng"), b"one").unwrap();
+        write(dir.path().join("B.dng"), b"changed").unwrap();
+
+        let path = manifest.write_to(dir.path(), true).unwrap();
+        assert!(path.with_extension("mhl").exists());
+
+        let text = read_to_string(&path).unwrap();
+        let outcomes = check(parse(&text, dir.path()).unwrap());
+
+        assert!(matches!(outcomes[0].1, Outcome::Ok));
+        assert!(matches!(outcomes[1].1, Outcome::Mismatch));
+        assert!(matches!(outcomes[2].1, Outcome::Unreadable(_)));
+    }
+
+    #[test]
+    fn mhl_escapes_paths() {
+        let mhl = Manifest::new(vec![entry("R&D/<1>.dng", b"one")]).mhl();
+
+        assert!(mhl.contains("<path size=\"3\">R&amp;D/&lt;1&gt;.dng</path>"));
+        assert!(mhl.contains("<sha256 action=\"original\""));
+    }
+
+    #[test]
+    fn runs_in_the_same_second_get_their_own_manifest() {
+        let dir = tempdir().unwrap();
+        let mut first = Manifest::new(vec![entry("A.dng", b"one")]);
+        let mut second = Manifest::new(vec![entry("B.dng", b"two")]);
+        first.created = "2025-01-02T03:04:05.678Z".parse().unwrap();
+        second.created = first.created + Duration::from_millis(1);
+
+        let first = first.write_to(dir.path(), true).unwrap();
+        let second = second.write_to(dir.path(), true).unwrap();
+
+        assert_ne!(first, second);
+    }
+}
@@ -0,0 +1,327 @@
+// Copyright (c) Carter J. Canedy <cartercanedy42@gmail.com>
+// rawbit is free software, distributable under the terms of the MIT license
+// See https://raw.githubusercontent.com/cartercanedy/rawbit/refs/heads/master/LICENSE.txt
+
+use std::{
+    fs::{create_dir_all, hard_link, remove_file, rename, File},
+    io,
+    path::{Path, PathBuf},
+};
+
+use tempfile::NamedTempFile;
+
+use crate::{
+    common::{map_err, sync_dir},
+    job::Error,
+};
+
+/// Written to a temporary file next to the final path, which is synced and renamed into place by
+/// [`AtomicFile::commit`], or removed if it's dropped before that
+pub struct AtomicFile {
+    temp_file: NamedTempFile,
+    output_path: PathBuf,
+    overwrite: bool,
+}
+
+impl AtomicFile {
+    /// Fails early if `output_path` is taken and `overwrite` isn't set
+    pub fn create(output_path: &Path, overwrite: bool) -> Result<Self, Error> {
+        let output_dir = parent_dir(output_path);
+
+        map_err!(
+            create_dir_all(output_dir),
+            Error::Io,
+            format!("couldn't make output dir: {}", output_dir.display())
+        )?;
+
+        if output_path.is_dir() {
+            return Err(Error::AlreadyExists(format!(
+                "computed filepath already exists as a directory: {}",
+                output_path.display()
+            )));
+        } else if !overwrite && output_path.exists() {
+            return Err(already_exists(output_path));
+        }
+
+        // hidden, and without the final extension, so that leftovers of a killed process are
+        // never mistaken for finished output
+        let mut builder = tempfile::Builder::new();
+        builder.prefix(".rawbit-").suffix(".tmp");
+
+        // temporary files are private by default, but the output should end up with the same
+        // permissions as any other new file (0o666 is narrowed down by the umask)
+        #[cfg(unix)]
+        builder.permissions(std::os::unix::fs::PermissionsExt::from_mode(0o666));
+
+        let temp_file = map_err!(
+            builder.tempfile_in(output_dir),
+            Error::Io,
+            format!("couldn't create temporary file in {}", output_dir.display())
+        )?;
+
+        Ok(Self {
+            temp_file,
+            output_path: output_path.to_path_buf(),
+            overwrite,
+        })
+    }
+
+    pub fn as_file_mut(&mut self) -> &mut File {
+        self.temp_file.as_file_mut()
+    }
+
+    pub fn commit(self) -> Result<(), Error> {
+        let Self {
+            temp_file,
+            output_path,
+            overwrite,
+        } = self;
+
+        map_err!(
+            temp_file.as_file().sync_all(),
+            Error::Io,
+            format!(
+                "couldn't sync output file to disk: {}",
+                output_path.display()
+            )
+        )?;
+
+        let persisted = if overwrite {
+            temp_file.persist(&output_path)
+        } else {
+            temp_file.persist_noclobber(&output_path)
+        };
+
+        match persisted {
+            Ok(_) => {}
+            Err(err) if err.error.kind() == io::ErrorKind::AlreadyExists => {
+                return Err(already_exists(&output_path));
+            }
+            Err(err) => {
+                return Err(Error::Io(
+                    format!(
+                        "couldn't move output file into place: {}",
+                        output_path.display()
+                    ),
+                    err.error,
+                ));
+            }
+        }
+
+        let output_dir = parent_dir(&output_path);
+        map_err!(
+            sync_dir(output_dir),
+            Error::Io,
+            format!("couldn't sync output dir to disk: {}", output_dir.display())
+        )
+    }
+
+    /// A file of the same name in `dir` is replaced
+    pub fn quarantine(self, dir: &Path) -> Result<PathBuf, Error> {
+        let quarantine_path = dir.join(self.output_path.file_name().unwrap_or_default());
+
+        map_err!(
+            create_dir_all(dir),
+            Error::Io,
+            format!("couldn't make quarantine dir: {}", dir.display())
+        )?;
+
+        match self.temp_file.persist(&quarantine_path) {
+            Ok(_) => Ok(quarantine_path),
+            Err(err) => Err(Error::Io(
+                format!(
+                    "couldn't quarantine output file: {}",
+                    quarantine_path.display()
+                ),
+                err.error,
+            )),
+        }
+    }
+}
+
+/// Never replaces an existing file; falls back to copying across filesystems. Where hard links
+/// aren't supported, i.e. on FAT, a file that appears at `to` between the check and the rename is
+/// replaced.
+pub fn move_file(from: &Path, to: &Path) -> Result<(), Error> {
+    let output_dir = parent_dir(to);
+
+    map_err!(
+        create_dir_all(output_dir),
+        Error::Io,
+        format!("couldn't make output dir: {}", output_dir.display())
+    )?;
+
+    // unlike `rename`, linking fails if `to` is taken
+    match hard_link(from, to) {
+        Ok(()) => remove_moved(from)?,
+        Err(err) if err.kind() == io::ErrorKind::AlreadyExists => return Err(already_exists(to)),
+        Err(err) if err.kind() == io::ErrorKind::CrossesDevices => {
+            copy_file(from, to)?;
+            remove_moved(from)?;
+        }
+        Err(_) if to.symlink_metadata().is_ok() => return Err(already_exists(to)),
+        Err(_) => map_err!(
+            rename(from, to),
+            Error::Io,
+            format!("couldn't move {} to {}", from.display(), to.display())
+        )?,
+    }
+
+    for dir in [output_dir, parent_dir(from)] {
+        map_err!(
+            sync_dir(dir),
+            Error::Io,
+            format!("couldn't sync dir to disk: {}", dir.display())
+        )?;
+    }
+
+    Ok(())
+}
+
+fn remove_moved(from: &Path) -> Result<(), Error> {
+    map_err!(
+        remove_file(from),
+        Error::Io,
+        format!("couldn't remove {} after moving it", from.display())
+    )
+}
+
+/// Never replaces an existing file
+pub fn copy_file(from: &Path, to: &Path) -> Result<(), Error> {
+    let mut output = AtomicFile::create(to, false)?;
+    let mut input = map_err!(
+        File::open(from),
+        Error::Io,
+        format!("couldn't open {}", from.display())
+    )?;
+
+    map_err!(
+        io::copy(&mut input, output.as_file_mut()),
+        Error::Io,
+        format!("couldn't copy {} to {}", from.display(), to.display())
+    )?;
+
+    output.commit()
+}
+
+/// `.` for a bare filename
+pub fn parent_dir(path: &Path) -> &Path {
+    match path.parent() {
+        Some(dir) if !dir.as_os_str().is_empty() => dir,
+        _ => Path::new("."),
+    }
+}
+
+fn already_exists(output_path: &Path) -> Error {
+    Error::AlreadyExists(format!(
+        "won't overwrite existing file: {}",
+        output_path.display()
+    ))
+}
+
+#[cfg(test)]
+mod test_output {
+    use std::{
+        fs::{metadata, read, read_dir, write},
+        io::Write as _,
+    };
+
+    use tempfile::tempdir;
+
+    use super::{move_file, AtomicFile};
+    use crate::job::Error;
+
+    #[test]
+    fn writes_nothing_until_committed() {
+        let dir = tempdir().unwrap();
+        let output_path = dir.path().join("nested/out.dng");
+
+        let mut output = AtomicFile::create(&output_path, false).unwrap();
+        output.as_file_mut().write_all(b"half a DNG").unwrap();
+        assert!(!output_path.exists());
+
+        drop(output);
+        assert_eq!(read_dir(dir.path().join("nested")).unwrap().count(), 0);
+    }
+
+    #[test]
+    fn keeps_existing_file_unless_overwriting() {
+        let dir = tempdir().unwrap();
+        let output_path = dir.path().join("out.dng");
+        write(&output_path, b"old").unwrap();
+
+        assert!(matches!(
+            AtomicFile::create(&output_path, false),
+            Err(Error::AlreadyExists(_))
+        ));
+
+        let mut output = AtomicFile::create(&output_path, true).unwrap();
+        output.as_file_mut().write_all(b"new").unwrap();
+        output.commit().unwrap();
+
+        assert_eq!(read(&output_path).unwrap(), b"new");
+        assert_eq!(read_dir(dir.path()).unwrap().count(), 1);
+    }
+
+    #[test]
+    fn output_has_default_permissions() {
+        let dir = tempdir().unwrap();
+        let output_path = dir.path().join("out.dng");
+        let reference_path = dir.path().join("reference");
+        write(&reference_path, b"").unwrap();
+
+        AtomicFile::create(&output_path, false)
+            .unwrap()
+            .commit()
+            .unwrap();
+
+        assert_eq!(
+            metadata(output_path).unwrap().permissions(),
+            metadata(reference_path).unwrap().permissions()
+        );
+    }
+
+    #[test]
+    fn loses_race_for_output_path() {
+        let dir = tempdir().unwrap();
+        let output_path = dir.path().join("out.dng");
+
+        let output = AtomicFile::create(&output_path, false).unwrap();
+        write(&output_path, b"other").unwrap();
+
+        assert!(matches!(output.commit(), Err(Error::AlreadyExists(_))));
+        assert_eq!(read(&output_path).unwrap(), b"other");
+        assert_eq!(read_dir(dir.path()).unwrap().count(), 1);
+    }
+
+    #[test]
+    fn quarantines_outside_output_dir() {
+        let dir = tempdir().unwrap();
+        let output_path = dir.path().join("out/a.dng");
+
+        let mut output = AtomicFile::create(&output_path, false).unwrap();
+        output.as_file_mut().write_all(b"corrupt").unwrap();
+
+        let quarantined = output.quarantine(&dir.path().join("bad")).unwrap();
+
+        assert_eq!(quarantined, dir.path().join("bad/a.dng"));
+        assert_eq!(read(quarantined).unwrap(), b"corrupt");
+        assert_eq!(read_dir(dir.path().join("out")).unwrap().count(), 0);
+    }
+
+    #[test]
+    fn moves_files_without_replacing_any() {
+        let dir = tempdir().unwrap();
+        let (a, b) = (dir.path().join("a.dng"), dir.path().join("nested/b.dng"));
+        write(&a, b"a").unwrap();
+
+        move_file(&a, &b).unwrap();
+        assert!(!a.exists());
+        assert_eq!(read(&b).unwrap(), b"a");
+
+        write(&a, b"another a").unwrap();
+        assert!(matches!(move_file(&a, &b), Err(Error::AlreadyExists(_))));
+        assert_eq!(read(&b).unwrap(), b"a");
+        assert!(a.exists());
+    }
+}
@@ -2,22 +2,25 @@
 // rawbit is free software, distributable under the terms of the MIT license
 // See https://raw.githubusercontent.com/cartercanedy/rawbit/refs/heads/master/LICENSE.txt
 
-use std::{borrow::Cow, cell::LazyCell, error, fmt};
+use std::{borrow::Cow, cell::LazyCell, error, fmt, path::PathBuf};
 
-use chrono::NaiveDateTime;
+use chrono::{format::StrftimeItems, NaiveDateTime, Timelike as _};
 use phf::{phf_map, Map};
-use rawler::decoders::RawMetadata;
-use smlog::warn;
+use rawler::{decoders::RawMetadata, RawImage};
 use zips::zip;
 
-use crate::common::{AppError, RawbitResult};
+use crate::{
+    common::{AppError, RawbitResult},
+    sanitize::Profile,
+};
 
 const OPEN_EXPANSION: char = '{';
 const CLOSE_EXPANSION: char = '}';
+const PATH_SEPARATOR: char = '/';
 const EXIF_DT_FMT: &str = "%Y:%m:%d %H:%M:%S";
 
 const IMG_ORIG_FNAME_ITEM: FmtItem<'static> =
-    FmtItem::Metadata(MetadataKind::ImageOriginalFilename);
+    FmtItem::Metadata(MetadataKind::ImageOriginalFilename, Vec::new());
 
 const MD_KIND_MAP: Map<&str, MetadataKind> = const {
     use MetadataKind::*;
@@ -27,7 +30,7 @@ const MD_KIND_MAP: Map<&str, MetadataKind> = const {
         "camera.shutter_speed" => CameraShutterSpeed,
         "camera.iso" => CameraISO,
         "camera.exposure_compensation" => CameraExposureComp,
-        "camea.flash" => CameraFlash,
+        "camera.flash" => CameraFlash,
         "lens.make" => LensMake,
         "lens.model" => LensModel,
         "lens.focal_length" => LensFocalLength,
@@ -38,6 +41,7 @@ const MD_KIND_MAP: Map<&str, MetadataKind> = const {
         "image.bit_depth" => ImageBitDepth,
         "image.color_space" => ImageColorSpace,
         "image.sequence_number" => ImageSequenceNumber,
+        "image.index" => ImageIndex,
         "image.original_filename" => ImageOriginalFilename
     }
 };
@@ -61,14 +65,49 @@ pub enum MetadataKind {
     ImageHeight,
     ImageWidth,
     ImageBitDepth,
+    ImageIndex,
     ImageOriginalFilename,
 }
 
 impl MetadataKind {
-    pub fn expand_with_metadata<'a>(self, md: &'a RawMetadata, original: &str) -> Cow<'a, str> {
+    /// In the order they're listed by `rawbit inspect`
+    pub const ALL: [Self; 18] = {
+        use MetadataKind::*;
+        [
+            CameraMake,
+            CameraModel,
+            CameraShutterSpeed,
+            CameraISO,
+            CameraExposureComp,
+            CameraFlash,
+            LensMake,
+            LensModel,
+            LensFocalLength,
+            LensFocusDist,
+            LensFStop,
+            ImageWidth,
+            ImageHeight,
+            ImageBitDepth,
+            ImageColorSpace,
+            ImageSequenceNumber,
+            ImageIndex,
+            ImageOriginalFilename,
+        ]
+    };
+
+    pub fn name(self) -> &'static str {
+        MD_KIND_MAP
+            .entries()
+            .find_map(|(name, kind)| (*kind == self).then_some(*name))
+            .unwrap_or_default()
+    }
+
+    pub fn expand_with_metadata<'a>(self, ctx: &RenderContext<'a>) -> Cow<'a, str> {
         use MetadataKind::*;
         type CowStr<'a> = Cow<'a, str>;
 
+        let md = ctx.md;
+
         match self {
             CameraMake => CowStr::Borrowed(&md.make),
             CameraModel => CowStr::Borrowed(&md.model),
@@ -87,6 +126,29 @@ impl MetadataKind {
                     .map_or(const { String::new() }, ToString::to_string),
             ),
 
+            CameraExposureComp => CowStr::Owned(
+                md.exif
+                    .exposure_bias
+                    .as_ref()
+                    .filter(|bias| bias.d != 0)
+                    .map_or(const { String::new() }, |bias| {
+                        match decimal(f64::from(bias.n), f64::from(bias.d)) {
+                            zero if zero == "0" => zero,
+                            neg if neg.starts_with('-') => neg,
+                            pos => format!("+{pos}"),
+                        }
+                    }),
+            ),
+
+            // bit 0 of the EXIF flash tag is set when the flash fired
+            CameraFlash => CowStr::Borrowed(md.exif.flash.map_or("", |flash| {
+                if flash & 1 == 1 {
+                    "flash"
+                } else {
+                    "noflash"
+                }
+            })),
+
             LensMake => CowStr::Borrowed(md.exif.lens_make.as_ref().map_or("", |s| s.as_ref())),
 
             LensModel => CowStr::Borrowed(md.exif.lens_model.as_ref().map_or("", |s| s.as_ref())),
@@ -100,71 +162,375 @@ impl MetadataKind {
                     }),
             ),
 
-            ImageOriginalFilename => CowStr::Owned(original.to_string()),
+            // a subject distance of 0xFFFFFFFF means infinity, 0 means unknown
+            LensFocusDist => CowStr::Owned(md.exif.subject_distance.as_ref().map_or(
+                const { String::new() },
+                |dist| match (dist.n, dist.d) {
+                    (u32::MAX, _) => "inf".into(),
+                    (0, _) | (_, 0) => String::new(),
+                    (n, d) => decimal(f64::from(n), f64::from(d)) + "m",
+                },
+            )),
+
+            LensFStop => CowStr::Owned(
+                md.exif
+                    .fnumber
+                    .as_ref()
+                    .filter(|f| f.d != 0)
+                    .map_or(const { String::new() }, |f| {
+                        decimal(f64::from(f.n), f64::from(f.d))
+                    }),
+            ),
 
-            _ => {
-                warn!("using unimplemented metadata tag: {}", self.as_str());
-                CowStr::Borrowed("")
+            ImageWidth => CowStr::Owned(ctx.image.width.to_string()),
+            ImageHeight => CowStr::Owned(ctx.image.height.to_string()),
+            ImageBitDepth => CowStr::Owned(ctx.image.bit_depth.to_string()),
+            ImageIndex => CowStr::Owned(ctx.image.index.to_string()),
+
+            ImageColorSpace => CowStr::Owned(md.exif.color_space.map_or(
+                const { String::new() },
+                |space| match space {
+                    1 => "sRGB".into(),
+                    2 => "AdobeRGB".into(),
+                    0xFFFF => "uncalibrated".into(),
+                    other => other.to_string(),
+                },
+            )),
+
+            ImageSequenceNumber => CowStr::Owned(
+                md.exif
+                    .image_number
+                    .as_ref()
+                    .map_or(const { String::new() }, ToString::to_string),
+            ),
+
+            ImageOriginalFilename => CowStr::Borrowed(ctx.original_filename),
+        }
+    }
+}
+
+/// At most one decimal place, without a trailing `.0`
+fn decimal(n: f64, d: f64) -> String {
+    let rendered = format!("{:.1}", n / d);
+
+    match rendered.strip_suffix(".0") {
+        Some("-0") => "0".into(),
+        Some(whole) => whole.into(),
+        None => rendered,
+    }
+}
+
+/// Read from the decoded raw image, since they aren't part of the EXIF block
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct ImageInfo {
+    pub width: usize,
+    pub height: usize,
+    pub bit_depth: usize,
+    /// which of the images in the RAW file this is, starting at 0
+    pub index: usize,
+}
+
+impl From<&RawImage> for ImageInfo {
+    fn from(image: &RawImage) -> Self {
+        Self {
+            width: image.width,
+            height: image.height,
+            bit_depth: image.bps,
+            index: 0,
+        }
+    }
+}
+
+/// Assigned to each image in capture-time order, starting at 1
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Counter {
+    /// `{seq}`: counts every image in the run
+    Run,
+    /// `{seq.day}`: restarts for each capture date
+    Day,
+    /// `{seq.dir}`: restarts for each output directory
+    Dir,
+}
+
+impl Counter {
+    fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "seq" => Some(Self::Run),
+            "seq.day" => Some(Self::Day),
+            "seq.dir" => Some(Self::Dir),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Counters {
+    pub run: usize,
+    pub day: usize,
+    pub dir: usize,
+}
+
+impl Counters {
+    const fn get(self, counter: Counter) -> usize {
+        match counter {
+            Counter::Run => self.run,
+            Counter::Day => self.day,
+            Counter::Dir => self.dir,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct RenderContext<'a> {
+    pub original_filename: &'a str,
+    pub md: &'a RawMetadata,
+    pub image: ImageInfo,
+    pub counters: Counters,
+}
+
+/// With sub-second precision if the camera recorded it
+pub fn capture_time(md: &RawMetadata) -> Option<NaiveDateTime> {
+    let date_str = md.exif.date_time_original.as_ref()?;
+    let date = NaiveDateTime::parse_from_str(date_str, EXIF_DT_FMT).ok()?;
+
+    let nanos = md.exif.sub_sec_time_original.as_ref().and_then(|subsec| {
+        // "12" means 0.12s, so pad on the right to get nanoseconds
+        format!("{:0<9.9}", subsec.trim()).parse::<u32>().ok()
+    });
+
+    Some(nanos.and_then(|n| date.with_nanosecond(n)).unwrap_or(date))
+}
+
+/// Written the way a camera would, so that the date expands like any other
+pub fn set_capture_time(md: &mut RawMetadata, time: NaiveDateTime) {
+    md.exif.date_time_original = Some(time.format(EXIF_DT_FMT).to_string());
+    md.exif.sub_sec_time_original = Some(format!("{:03}", time.nanosecond() / 1_000_000));
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Filter<'a> {
+    Lower,
+    Upper,
+    /// lowercase, with every run of non-alphanumeric characters replaced by a single `-`
+    Slug,
+    /// keep at most this many characters
+    Trunc(usize),
+    Replace(Cow<'a, str>, Cow<'a, str>),
+}
+
+impl<'a> Filter<'a> {
+    fn parse(s: &'a str) -> Option<Self> {
+        let (name, args) = match s.split_once('(') {
+            Some((name, args)) => (name, Some(args.strip_suffix(')')?)),
+            None => (s, None),
+        };
+
+        match (name, args) {
+            ("lower", None) => Some(Self::Lower),
+            ("upper", None) => Some(Self::Upper),
+            ("slug", None) => Some(Self::Slug),
+            ("trunc", Some(len)) => len.trim().parse().ok().map(Self::Trunc),
+            ("replace", Some(args)) => {
+                let (from, to) = args.split_once(',')?;
+                (!from.is_empty()).then(|| Self::Replace(from.into(), to.into()))
             }
+            _ => None,
         }
     }
 
-    fn as_str(self) -> &'static str {
-        MD_KIND_MAP.entries().find(|(_, v)| **v == self).unwrap().0
+    fn apply<'r>(&self, value: Cow<'r, str>) -> Cow<'r, str> {
+        match self {
+            Self::Lower => Cow::Owned(value.to_lowercase()),
+            Self::Upper => Cow::Owned(value.to_uppercase()),
+
+            Self::Slug => {
+                let slug = value
+                    .split(|c: char| !c.is_alphanumeric())
+                    .filter(|word| !word.is_empty())
+                    .collect::<Vec<_>>()
+                    .join("-");
+
+                Cow::Owned(slug.to_lowercase())
+            }
+
+            Self::Trunc(len) => match value.char_indices().nth(*len) {
+                Some((end, _)) => Cow::Owned(value[..end].to_string()),
+                None => value,
+            },
+
+            Self::Replace(from, to) => Cow::Owned(value.replace(from.as_ref(), to)),
+        }
     }
 }
 
+/// Ignores separators in between parentheses
+fn split_unparenthesized(s: &str, sep: char) -> impl Iterator<Item = &str> {
+    let mut depth = 0_usize;
+
+    s.split(move |c: char| {
+        match c {
+            '(' => depth += 1,
+            ')' => depth = depth.saturating_sub(1),
+            _ => (),
+        }
+
+        c == sep && depth == 0
+    })
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum Fallback<'a> {
+    /// `{lens.model|nolens}`: render this value instead
+    Default(Cow<'a, str>),
+    /// `{lens.model?}`: render nothing, and drop the literal that immediately follows
+    Omit,
+}
+
 #[derive(Debug, PartialEq, Eq)]
 pub enum FmtItem<'a> {
     Literal(Cow<'a, str>),
     DateTime(Cow<'a, str>),
-    Metadata(MetadataKind),
+    Metadata(MetadataKind, Vec<Filter<'a>>),
+    Fallback(Box<Self>, Fallback<'a>),
+    /// zero-padded to the given width
+    Counter(Counter, usize),
+    /// `/`, separates the directories of the output path
+    Separator,
+}
+
+impl FmtItem<'_> {
+    fn render<'r, F>(
+        &'r self,
+        ctx: &RenderContext<'r>,
+        date: &LazyCell<Option<NaiveDateTime>, F>,
+    ) -> Cow<'r, str>
+    where
+        F: FnOnce() -> Option<NaiveDateTime>,
+    {
+        match self {
+            FmtItem::Literal(lit) => Cow::Borrowed(lit.as_ref()),
+            FmtItem::Metadata(md_kind, filters) => filters
+                .iter()
+                .fold(md_kind.expand_with_metadata(ctx), |value, filter| {
+                    filter.apply(value)
+                }),
+
+            FmtItem::DateTime(item) => date.as_ref().map_or(Cow::Borrowed(""), |date| {
+                Cow::Owned(date.format(item.as_ref()).to_string())
+            }),
+
+            FmtItem::Separator => Cow::Borrowed(""),
+
+            FmtItem::Counter(counter, width) => {
+                Cow::Owned(format!("{:0width$}", ctx.counters.get(*counter)))
+            }
+
+            FmtItem::Fallback(item, fallback) => match (item.render(ctx, date), fallback) {
+                (rendered, Fallback::Default(default)) if rendered.is_empty() => {
+                    Cow::Borrowed(default.as_ref())
+                }
+                (rendered, _) => rendered,
+            },
+        }
+    }
+
+    fn expands(&self, kind: MetadataKind) -> bool {
+        match self {
+            FmtItem::Metadata(md_kind, _) => *md_kind == kind,
+            FmtItem::Fallback(item, _) => item.expands(kind),
+            _ => false,
+        }
+    }
+
+    fn missing(
+        &self,
+        ctx: &RenderContext,
+        captured: Option<NaiveDateTime>,
+    ) -> Option<&'static str> {
+        match self {
+            FmtItem::Metadata(kind, _) => kind
+                .expand_with_metadata(ctx)
+                .is_empty()
+                .then(|| kind.name()),
+            FmtItem::DateTime(_) => captured.is_none().then_some("capture time"),
+            FmtItem::Fallback(item, _) => item.missing(ctx, captured),
+            _ => None,
+        }
+    }
+
+    fn is_dir_counter(&self) -> bool {
+        match self {
+            FmtItem::Counter(counter, _) => *counter == Counter::Dir,
+            FmtItem::Fallback(item, _) => item.is_dir_counter(),
+            _ => false,
+        }
+    }
 }
 
 #[derive(Debug)]
-pub struct FilenameFormat<'a>(Box<[FmtItem<'a>]>);
+pub struct FilenameFormat<'a>(Box<[FmtItem<'a>]>, Profile);
 
 impl<'a> FilenameFormat<'a> {
-    pub fn render_filename(&self, original_filename: &str, md: &RawMetadata) -> String {
-        let mut fname_str = String::new();
+    /// Sanitizes every rendered path component for the given filesystem
+    #[must_use]
+    pub const fn with_profile(mut self, profile: Profile) -> Self {
+        self.1 = profile;
+        self
+    }
+
+    /// Components that render to an empty string are skipped
+    pub fn render_dir(&self, ctx: &RenderContext) -> PathBuf {
+        let mut components = self.components().collect::<Vec<_>>();
+        components.pop();
+
+        components
+            .into_iter()
+            .map(|component| render_component(component, ctx))
+            .filter(|component| !component.is_empty())
+            .map(|component| match self.1.sanitize(&component, "") {
+                dots if dots == "." || dots == ".." => "_".into(),
+                sanitized => sanitized,
+            })
+            .collect()
+    }
 
-        let date = LazyCell::new(Box::new(move || {
-            let date_str = &md.exif.date_time_original.clone().unwrap_or_default();
-            NaiveDateTime::parse_from_str(date_str, EXIF_DT_FMT).ok()
-        }));
+    pub fn render_filename(&self, ctx: &RenderContext, extension: &str) -> String {
+        let filename = render_component(self.components().last().unwrap_or_default(), ctx);
+        self.1.sanitize(&filename, extension) + extension
+    }
 
-        for atom in &self.0 {
-            let rendered = match atom {
-                FmtItem::Literal(lit) => lit.clone(),
-                FmtItem::Metadata(md_kind) => md_kind.expand_with_metadata(md, original_filename),
+    pub fn expands(&self, kind: MetadataKind) -> bool {
+        self.0.iter().any(|item| item.expands(kind))
+    }
 
-                FmtItem::DateTime(item) => date.as_ref().map_or(Cow::Borrowed(""), |date| {
-                    Cow::Owned(date.format(item.as_ref()).to_string())
-                }),
-            };
+    /// Includes the values a fallback covers for
+    pub fn missing(&self, ctx: &RenderContext) -> Vec<&'static str> {
+        let captured = capture_time(ctx.md);
+        let mut missing = vec![];
 
-            fname_str.push_str((rendered).as_ref());
+        for name in self.0.iter().filter_map(|item| item.missing(ctx, captured)) {
+            if !missing.contains(&name) {
+                missing.push(name);
+            }
         }
 
-        fname_str
+        missing
     }
 
-    pub fn parse(fmt: &'a str) -> RawbitResult<Self> {
-        #[derive(Debug)]
-        enum ScanState {
-            Start,
-            Literal,
-            DateTime,
-            ExpansionStart,
-            ExpansionBody,
-        }
+    fn components(&self) -> impl Iterator<Item = &[FmtItem<'a>]> {
+        self.0.split(|item| *item == FmtItem::Separator)
+    }
 
+    pub fn parse(fmt: &'a str) -> RawbitResult<Self> {
         let mut items = vec![];
         let mut to_parse = fmt;
 
         let mut consumed = 0;
         let mut state = ScanState::Start;
 
+        // where the current path component starts in `fmt`, and the index of its first item
+        let mut component = (0, 0);
+
         while !to_parse.is_empty() {
             let mut end = false;
             let split_at = to_parse
@@ -176,10 +542,11 @@ impl<'a> FilenameFormat<'a> {
                         _ if end => false,
 
                         (Start, sym) => {
-                            state = match sym {
-                                '%' => DateTime,
-                                &OPEN_EXPANSION => ExpansionStart,
-                                _ => Literal,
+                            (state, end) = match sym {
+                                '%' => (DateTime, false),
+                                &OPEN_EXPANSION => (ExpansionStart, false),
+                                &PATH_SEPARATOR => (Separator, true),
+                                _ => (Literal, false),
                             };
 
                             true
@@ -200,7 +567,7 @@ impl<'a> FilenameFormat<'a> {
                             true
                         }
 
-                        (Literal, '%' | &OPEN_EXPANSION) => false,
+                        (Literal, '%' | &OPEN_EXPANSION | &PATH_SEPARATOR) => false,
 
                         _ => true,
                     }
@@ -216,40 +583,14 @@ impl<'a> FilenameFormat<'a> {
                 if s == "{{" {
                     items.push(FmtItem::Literal(Cow::Borrowed(&s[0..1])));
                 } else {
-                    items.push(match state {
-                        ScanState::Literal => FmtItem::Literal(Cow::Borrowed(s)),
-
-                        ScanState::DateTime => {
-                            if s.len() != 2 {
-                                return Err(AppError::FmtStrParse(Error::invalid_expansion(
-                                    consumed,
-                                    s.len(),
-                                    to_parse,
-                                )));
-                            }
-
-                            FmtItem::DateTime(Cow::Borrowed(s))
-                        }
-
-                        ScanState::ExpansionBody => {
-                            assert!(
-                                s.starts_with(OPEN_EXPANSION),
-                                "An expansion was interpreted incorrectly: fmt: {to_parse}, seq: {s}"
-                            );
+                    let item = parse_item(&state, s, consumed, fmt)?;
 
-                            if s.ends_with(CLOSE_EXPANSION) {
-                                expand(&s[1..s.len() - 1]).ok_or(AppError::FmtStrParse(
-                                    Error::invalid_expansion(consumed, s.len(), to_parse),
-                                ))?
-                            } else {
-                                return Err(AppError::FmtStrParse(
-                                    Error::unterminated_expansion(consumed, s.len(), to_parse),
-                                ));
-                            }
-                        }
+                    if item == FmtItem::Separator {
+                        check_dir_component(&items[component.1..], component.0, consumed, fmt)?;
+                        component = (consumed + 1, items.len() + 1);
+                    }
 
-                        _ => unreachable!(),
-                    });
+                    items.push(item);
                 }
 
                 consumed += s.len();
@@ -265,24 +606,180 @@ impl<'a> FilenameFormat<'a> {
             state = ScanState::Start;
         }
 
-        if !items.contains(&IMG_ORIG_FNAME_ITEM) {
+        if items.last() == Some(&FmtItem::Separator) {
+            return Err(AppError::FmtStrParse(Error::invalid_path(
+                fmt.len() - 1,
+                1,
+                fmt,
+            )));
+        }
+
+        if !items[component.1..]
+            .iter()
+            .any(|item| item.expands(MetadataKind::ImageOriginalFilename))
+        {
             items.push(IMG_ORIG_FNAME_ITEM);
         }
 
-        Ok(Self(items.into_boxed_slice()))
+        Ok(Self(items.into_boxed_slice(), Profile::native()))
+    }
+}
+
+#[derive(Debug)]
+enum ScanState {
+    Start,
+    Literal,
+    DateTime,
+    ExpansionStart,
+    ExpansionBody,
+    Separator,
+}
+
+fn parse_item<'a>(
+    state: &ScanState,
+    s: &'a str,
+    consumed: usize,
+    fmt: &str,
+) -> RawbitResult<FmtItem<'a>> {
+    match state {
+        ScanState::Literal => Ok(FmtItem::Literal(Cow::Borrowed(s))),
+        ScanState::Separator => Ok(FmtItem::Separator),
+
+        ScanState::DateTime => {
+            if s.len() != 2 {
+                return Err(AppError::FmtStrParse(Error::invalid_expansion(
+                    consumed,
+                    s.len(),
+                    fmt,
+                )));
+            }
+
+            Ok(FmtItem::DateTime(Cow::Borrowed(s)))
+        }
+
+        ScanState::ExpansionBody => {
+            assert!(
+                s.starts_with(OPEN_EXPANSION),
+                "An expansion was interpreted incorrectly: fmt: {fmt}, seq: {s}"
+            );
+
+            if s.ends_with(CLOSE_EXPANSION) {
+                expand(&s[1..s.len() - 1]).ok_or(AppError::FmtStrParse(Error::invalid_expansion(
+                    consumed,
+                    s.len(),
+                    fmt,
+                )))
+            } else {
+                Err(AppError::FmtStrParse(Error::unterminated_expansion(
+                    consumed,
+                    s.len(),
+                    fmt,
+                )))
+            }
+        }
+
+        _ => unreachable!(),
+    }
+}
+
+fn check_dir_component(items: &[FmtItem], start: usize, end: usize, fmt: &str) -> RawbitResult<()> {
+    let is_invalid = match items {
+        [] => true,
+        [FmtItem::Literal(lit)] => lit == "." || lit == "..",
+        _ => items.iter().any(FmtItem::is_dir_counter),
+    };
+
+    if is_invalid {
+        let width = (end - start).max(1);
+        Err(AppError::FmtStrParse(Error::invalid_path(
+            start, width, fmt,
+        )))
+    } else {
+        Ok(())
     }
 }
 
-#[inline]
-fn expand(s: &str) -> Option<FmtItem> {
-    Some(FmtItem::Metadata(MD_KIND_MAP.get(s)?.to_owned()))
+/// Expanded values can't introduce path separators of their own
+fn render_component(items: &[FmtItem], ctx: &RenderContext) -> String {
+    let mut fname_str = String::new();
+    let md = ctx.md;
+
+    let date = LazyCell::new(move || capture_time(md));
+
+    let mut omit_literal = false;
+
+    for atom in items {
+        if std::mem::take(&mut omit_literal) && matches!(atom, FmtItem::Literal(..)) {
+            continue;
+        }
+
+        let rendered = atom.render(ctx, &date);
+
+        omit_literal = rendered.is_empty() && matches!(atom, FmtItem::Fallback(_, Fallback::Omit));
+
+        if matches!(atom, FmtItem::Literal(..)) {
+            fname_str.push_str(rendered.as_ref());
+        } else {
+            fname_str.push_str(&rendered.replace(['/', '\\'], "_"));
+        }
+    }
+
+    fname_str
+}
+
+fn expand(s: &str) -> Option<FmtItem<'_>> {
+    let expansion = split_unparenthesized(s, '|').next()?;
+
+    // everything after the first unparenthesized `|` is the default value
+    if let Some(default) = s.get(expansion.len() + 1..) {
+        return Some(FmtItem::Fallback(
+            Box::new(expand(expansion)?),
+            Fallback::Default(Cow::Borrowed(default)),
+        ));
+    }
+
+    if let Some(expansion) = s.strip_suffix('?') {
+        return Some(FmtItem::Fallback(
+            Box::new(expand(expansion)?),
+            Fallback::Omit,
+        ));
+    }
+
+    // date/time formats can be wrapped in an expansion to give them a fallback, i.e. `{%Y|undated}`
+    if s.starts_with('%') {
+        let is_valid =
+            !StrftimeItems::new(s).any(|item| matches!(item, chrono::format::Item::Error));
+
+        return is_valid.then_some(FmtItem::DateTime(Cow::Borrowed(s)));
+    }
+
+    let mut parts = split_unparenthesized(s, ':');
+    let name = parts.next()?;
+
+    if let Some(counter) = Counter::from_name(name) {
+        let width = match (parts.next(), parts.next()) {
+            (None, _) => 0,
+            (Some(width), None) => width.parse().ok()?,
+            _ => return None,
+        };
+
+        return Some(FmtItem::Counter(counter, width));
+    }
+
+    let md_kind = MD_KIND_MAP.get(name)?.to_owned();
+    let filters = parts.map(Filter::parse).collect::<Option<Vec<_>>>()?;
+
+    Some(FmtItem::Metadata(md_kind, filters))
 }
 
 #[cfg(test)]
 mod test_parse {
-    use crate::parse::FilenameFormat;
+    use crate::{
+        common::AppError,
+        parse::{Error, ErrorKind, FilenameFormat},
+    };
 
-    use super::{FmtItem, MetadataKind, OPEN_EXPANSION};
+    use super::{Counter, Fallback, Filter, FmtItem, MetadataKind, OPEN_EXPANSION};
     #[test]
     fn parses_expansions_and_strftime_ok() {
         assert!(FilenameFormat::parse("%Y-%m-%d_{camera.make}").is_ok());
@@ -325,16 +822,417 @@ mod test_parse {
             parsed.0.as_ref(),
             &[
                 FmtItem::DateTime("%Y".into()),
-                FmtItem::Metadata(MetadataKind::ImageOriginalFilename)
+                FmtItem::Metadata(MetadataKind::ImageOriginalFilename, vec![])
+            ]
+        );
+    }
+
+    #[test]
+    fn parses_fallbacks() {
+        let parsed =
+            FilenameFormat::parse("{lens.model|nolens}{%Y|undated}{image.original_filename?}")
+                .unwrap();
+
+        assert_eq!(
+            parsed.0.as_ref(),
+            &[
+                FmtItem::Fallback(
+                    Box::new(FmtItem::Metadata(MetadataKind::LensModel, vec![])),
+                    Fallback::Default("nolens".into())
+                ),
+                FmtItem::Fallback(
+                    Box::new(FmtItem::DateTime("%Y".into())),
+                    Fallback::Default("undated".into())
+                ),
+                FmtItem::Fallback(
+                    Box::new(FmtItem::Metadata(
+                        MetadataKind::ImageOriginalFilename,
+                        vec![]
+                    )),
+                    Fallback::Omit
+                ),
+            ]
+        );
+    }
+
+    #[test]
+    fn parses_filters() {
+        let parsed = FilenameFormat::parse(
+            "{camera.model:lower:trunc(8)}{lens.model:replace(F2.8,f2_8):slug|nolens}",
+        )
+        .unwrap();
+
+        assert_eq!(
+            parsed.0[0],
+            FmtItem::Metadata(
+                MetadataKind::CameraModel,
+                vec![Filter::Lower, Filter::Trunc(8)]
+            )
+        );
+
+        assert_eq!(
+            parsed.0[1],
+            FmtItem::Fallback(
+                Box::new(FmtItem::Metadata(
+                    MetadataKind::LensModel,
+                    vec![Filter::Replace("F2.8".into(), "f2_8".into()), Filter::Slug]
+                )),
+                Fallback::Default("nolens".into())
+            )
+        );
+    }
+
+    #[test]
+    fn parses_counters() {
+        let parsed = FilenameFormat::parse("{seq:04}{seq.day:3}{seq.dir}").unwrap();
+
+        assert_eq!(
+            &parsed.0[..3],
+            &[
+                FmtItem::Counter(Counter::Run, 4),
+                FmtItem::Counter(Counter::Day, 3),
+                FmtItem::Counter(Counter::Dir, 0),
+            ]
+        );
+
+        assert!(FilenameFormat::parse("{seq:lower}").is_err());
+        assert!(FilenameFormat::parse("{seq.month}").is_err());
+    }
+
+    #[test]
+    fn parses_path_separators() {
+        let err = FilenameFormat::parse("%Y/{camera.model}/").unwrap_err();
+        assert!(matches!(
+            err,
+            AppError::FmtStrParse(Error {
+                kind: ErrorKind::InvalidPath,
+                start: 17,
+                ..
+            })
+        ));
+
+        let parsed = FilenameFormat::parse("%Y/{camera.model}/x").unwrap();
+
+        assert_eq!(
+            parsed.0.as_ref(),
+            &[
+                FmtItem::DateTime("%Y".into()),
+                FmtItem::Separator,
+                FmtItem::Metadata(MetadataKind::CameraModel, vec![]),
+                FmtItem::Separator,
+                FmtItem::Literal("x".into()),
+                FmtItem::Metadata(MetadataKind::ImageOriginalFilename, vec![]),
             ]
         );
     }
+
+    #[test]
+    fn fails_to_parse_invalid_path_components() {
+        for fmt in ["/%Y", "%Y//x", "%Y/", "../x", "%Y/./x", "{seq.dir}/x"] {
+            assert!(FilenameFormat::parse(fmt).is_err(), "{fmt}");
+        }
+    }
+
+    #[test]
+    fn fails_to_parse_unknown_filter() {
+        assert!(FilenameFormat::parse("{camera.model:lowercase}").is_err());
+        assert!(FilenameFormat::parse("{camera.model:trunc(x)}").is_err());
+        assert!(FilenameFormat::parse("{camera.model:replace(DSC)}").is_err());
+    }
+
+    #[test]
+    fn fails_to_parse_fallback_of_invalid_expansion() {
+        let err = FilenameFormat::parse("%Y_{lens.mdoel|nolens}").unwrap_err();
+
+        // rendering the error must point into the original format string
+        assert!(err.to_string().contains("{lens.mdoel|nolens}"));
+        assert!(FilenameFormat::parse("{%Q|undated}").is_err());
+    }
+}
+
+#[cfg(test)]
+mod test_expand {
+    use std::path::Path;
+
+    use rawler::{
+        decoders::RawMetadata,
+        exif::Exif,
+        formats::tiff::{Rational, SRational},
+    };
+
+    use super::{capture_time, Counters, FilenameFormat, ImageInfo, MetadataKind, RenderContext};
+    use crate::{fixtures, sanitize::Profile};
+
+    fn metadata() -> RawMetadata {
+        fixtures::metadata(
+            "Sony",
+            "ILCE-7RM5",
+            Exif {
+                exposure_bias: Some(SRational::new(-2, 3)),
+                flash: Some(0x19),
+                subject_distance: Some(Rational::new(150, 100)),
+                fnumber: Some(Rational::new(28, 10)),
+                color_space: Some(1),
+                image_number: Some(1234),
+                iso_speed: Some(400),
+                lens_model: Some("FE 24-70mm F2.8 GM II".into()),
+                ..Default::default()
+            },
+        )
+    }
+
+    fn context(md: &RawMetadata) -> RenderContext<'_> {
+        RenderContext {
+            original_filename: "DSC01234",
+            md,
+            image: ImageInfo {
+                width: 9568,
+                height: 6376,
+                bit_depth: 14,
+                index: 2,
+            },
+            counters: Counters {
+                run: 7,
+                day: 3,
+                dir: 12,
+            },
+        }
+    }
+
+    fn expand(kind: MetadataKind, md: &RawMetadata) -> String {
+        kind.expand_with_metadata(&context(md)).into_owned()
+    }
+
+    fn render(fmt: &str, md: &RawMetadata) -> String {
+        FilenameFormat::parse(fmt)
+            .unwrap()
+            .render_filename(&context(md), "")
+    }
+
+    #[test]
+    fn expands_exposure_compensation() {
+        let mut md = metadata();
+        assert_eq!(expand(MetadataKind::CameraExposureComp, &md), "-0.7");
+
+        md.exif.exposure_bias = Some(SRational::new(1, 1));
+        assert_eq!(expand(MetadataKind::CameraExposureComp, &md), "+1");
+
+        md.exif.exposure_bias = Some(SRational::new(0, 1));
+        assert_eq!(expand(MetadataKind::CameraExposureComp, &md), "0");
+
+        md.exif.exposure_bias = Some(SRational::new(1, 0));
+        assert_eq!(expand(MetadataKind::CameraExposureComp, &md), "");
+    }
+
+    #[test]
+    fn expands_flash() {
+        let mut md = metadata();
+        assert_eq!(expand(MetadataKind::CameraFlash, &md), "flash");
+
+        md.exif.flash = Some(0x10);
+        assert_eq!(expand(MetadataKind::CameraFlash, &md), "noflash");
+    }
+
+    #[test]
+    fn expands_focus_distance() {
+        let mut md = metadata();
+        assert_eq!(expand(MetadataKind::LensFocusDist, &md), "1.5m");
+
+        md.exif.subject_distance = Some(Rational::new(u32::MAX, 1));
+        assert_eq!(expand(MetadataKind::LensFocusDist, &md), "inf");
+    }
+
+    #[test]
+    fn expands_fstop() {
+        let mut md = metadata();
+        assert_eq!(expand(MetadataKind::LensFStop, &md), "2.8");
+
+        md.exif.fnumber = Some(Rational::new(8, 1));
+        assert_eq!(expand(MetadataKind::LensFStop, &md), "8");
+    }
+
+    #[test]
+    fn expands_image_dimensions() {
+        let md = metadata();
+        assert_eq!(expand(MetadataKind::ImageWidth, &md), "9568");
+        assert_eq!(expand(MetadataKind::ImageHeight, &md), "6376");
+    }
+
+    #[test]
+    fn expands_bit_depth() {
+        assert_eq!(expand(MetadataKind::ImageBitDepth, &metadata()), "14");
+    }
+
+    #[test]
+    fn expands_image_index() {
+        assert_eq!(expand(MetadataKind::ImageIndex, &metadata()), "2");
+        assert_eq!(
+            render("{image.original_filename}_{image.index}", &metadata()),
+            "DSC01234_2"
+        );
+    }
+
+    #[test]
+    fn expands_color_space() {
+        let mut md = metadata();
+        assert_eq!(expand(MetadataKind::ImageColorSpace, &md), "sRGB");
+
+        md.exif.color_space = Some(0xFFFF);
+        assert_eq!(expand(MetadataKind::ImageColorSpace, &md), "uncalibrated");
+    }
+
+    #[test]
+    fn expands_sequence_number() {
+        assert_eq!(
+            expand(MetadataKind::ImageSequenceNumber, &metadata()),
+            "1234"
+        );
+    }
+
+    #[test]
+    fn missing_fields_expand_to_nothing() {
+        let md = RawMetadata {
+            exif: Exif::default(),
+            ..metadata()
+        };
+
+        for kind in [
+            MetadataKind::CameraExposureComp,
+            MetadataKind::CameraFlash,
+            MetadataKind::LensFocusDist,
+            MetadataKind::LensFStop,
+            MetadataKind::ImageColorSpace,
+            MetadataKind::ImageSequenceNumber,
+        ] {
+            assert_eq!(expand(kind, &md), "");
+        }
+    }
+
+    #[test]
+    fn renders_default_for_missing_fields() {
+        const FMT: &str = "{%Y-%m-%d|undated}_{lens.make|nolens}_{lens.model|nolens}_";
+        let mut md = metadata();
+
+        assert_eq!(
+            render(FMT, &md),
+            "undated_nolens_FE 24-70mm F2.8 GM II_DSC01234"
+        );
+
+        md.exif.date_time_original = Some("2024:05:01 12:34:56".into());
+        assert_eq!(
+            render(FMT, &md),
+            "2024-05-01_nolens_FE 24-70mm F2.8 GM II_DSC01234"
+        );
+    }
+
+    #[test]
+    fn lists_missing_values_once() {
+        let md = metadata();
+        let format =
+            FilenameFormat::parse("%Y/{lens.make|nolens}_{lens.model}_{lens.make?}_{%d|x}")
+                .unwrap();
+
+        assert_eq!(format.missing(&context(&md)), ["capture time", "lens.make"]);
+    }
+
+    #[test]
+    fn applies_filters_in_order() {
+        let md = metadata();
+
+        assert_eq!(render("{camera.model:lower}_", &md), "ilce-7rm5_DSC01234");
+        assert_eq!(render("{camera.make:upper}_", &md), "SONY_DSC01234");
+        assert_eq!(
+            render("{lens.model:slug}_", &md),
+            "fe-24-70mm-f2-8-gm-ii_DSC01234"
+        );
+        assert_eq!(render("{camera.model:trunc(4)}_", &md), "ILCE_DSC01234");
+        assert_eq!(
+            render("{image.original_filename:replace(DSC,IMG)}", &md),
+            "IMG01234"
+        );
+        assert_eq!(render("{lens.model:trunc(5):slug}_", &md), "fe-24_DSC01234");
+    }
+
+    #[test]
+    #[allow(clippy::literal_string_with_formatting_args)]
+    fn renders_padded_counters() {
+        let md = metadata();
+
+        assert_eq!(
+            render("Smith_Wedding_{seq:04}", &md),
+            "Smith_Wedding_0007DSC01234"
+        );
+        assert_eq!(
+            render("{seq.day:03}_{seq.dir}_{image.original_filename}", &md),
+            "003_12_DSC01234"
+        );
+    }
+
+    #[test]
+    fn capture_time_includes_subseconds() {
+        let mut md = metadata();
+        assert_eq!(capture_time(&md), None);
+
+        md.exif.date_time_original = Some("2024:05:01 12:34:56".into());
+        md.exif.sub_sec_time_original = Some("25".into());
+
+        let captured = capture_time(&md).unwrap();
+        assert_eq!(captured.to_string(), "2024-05-01 12:34:56.250");
+    }
+
+    #[test]
+    fn expanded_values_cant_add_directories() {
+        let mut md = metadata();
+        md.model = "EOS R5 / C".into();
+        md.exif.lens_model = Some("..".into());
+
+        let format = FilenameFormat::parse("{lens.model}/{camera.model}/{camera.model}").unwrap();
+        let ctx = context(&md);
+
+        assert_eq!(format.render_dir(&ctx), Path::new("_").join("EOS R5 _ C"));
+        assert_eq!(
+            format.render_filename(&ctx, ".dng"),
+            "EOS R5 _ CDSC01234.dng"
+        );
+    }
+
+    #[test]
+    fn sanitizes_for_profile() {
+        let mut md = metadata();
+        md.model = "EOS R5: C.".into();
+
+        let format = FilenameFormat::parse("{camera.model}/con")
+            .unwrap()
+            .with_profile(Profile::Windows);
+        let ctx = context(&md);
+
+        assert_eq!(format.render_dir(&ctx), Path::new("EOS R5_ C"));
+        assert_eq!(format.render_filename(&ctx, ".dng"), "conDSC01234.dng");
+
+        let format = FilenameFormat::parse("{image.original_filename:replace(DSC01234,aux)}")
+            .unwrap()
+            .with_profile(Profile::Portable);
+
+        assert_eq!(format.render_filename(&ctx, ".dng"), "aux_.dng");
+    }
+
+    #[test]
+    fn omits_literal_after_missing_optional_field() {
+        const FMT: &str = "{camera.model}_{lens.make?}_{image.original_filename}";
+        let mut md = metadata();
+
+        assert_eq!(render(FMT, &md), "ILCE-7RM5_DSC01234");
+
+        md.exif.lens_make = Some("Sony".into());
+        assert_eq!(render(FMT, &md), "ILCE-7RM5_Sony_DSC01234");
+    }
 }
 
 #[derive(Clone, Copy, Debug)]
 pub enum ErrorKind {
     UnterminatedExpansion,
     InvalidExpansion,
+    InvalidPath,
     Unknown,
 }
 
@@ -386,6 +1284,14 @@ impl Error {
         Self::new(start, width, original, ErrorKind::InvalidExpansion)
     }
 
+    pub fn invalid_path<S: TryInto<u16>, W: TryInto<u16>>(
+        start: S,
+        width: W,
+        original: &str,
+    ) -> Self {
+        Self::new(start, width, original, ErrorKind::InvalidPath)
+    }
+
     fn print_error_details(&self, f: &mut fmt::Formatter<'_>, msg: &str) -> fmt::Result {
         let (start, width) = (self.start as usize, self.width as usize);
 
@@ -400,7 +1306,7 @@ impl Error {
 
 impl fmt::Display for Error {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        use ErrorKind::{InvalidExpansion, Unknown, UnterminatedExpansion};
+        use ErrorKind::{InvalidExpansion, InvalidPath, Unknown, UnterminatedExpansion};
 
         let (start, width, orig) = (self.start as usize, self.width as usize, &self.original);
 
@@ -411,6 +1317,7 @@ impl fmt::Display for Error {
             match self.kind {
                 UnterminatedExpansion => "unterminated variable expansion",
                 InvalidExpansion => "invalid variable expansion",
+                InvalidPath => "invalid path component",
                 Unknown => "unknown error",
             },
             err_seq
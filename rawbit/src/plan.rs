@@ -0,0 +1,1014 @@
+// Copyright (c) Carter J. Canedy <cartercanedy42@gmail.com>
+// rawbit is free software, distributable under the terms of the MIT license
+// See https://raw.githubusercontent.com/cartercanedy/rawbit/refs/heads/master/LICENSE.txt
+
+use std::{
+    borrow::Cow,
+    cmp::Ordering,
+    collections::{HashMap, HashSet},
+    fs::OpenOptions,
+    hash::Hash,
+    io::BufReader,
+    iter,
+    path::{Path, PathBuf},
+    time::SystemTime,
+};
+
+use chrono::{DateTime, Local, NaiveDateTime};
+use clap::ValueEnum;
+use rawler::{
+    decoders::{RawDecodeParams, RawMetadata},
+    exif::Exif,
+    get_decoder, RawFile,
+};
+
+use crate::{
+    args::IngestItem,
+    common::{map_err, sha256_file},
+    job::{Error, Mode},
+    parse::{capture_time, set_capture_time, Counters, FilenameFormat, ImageInfo, RenderContext},
+};
+
+#[derive(Clone, Debug)]
+pub struct Source {
+    pub input_path: PathBuf,
+    pub output_prefix: PathBuf,
+    pub md: RawMetadata,
+    pub image: ImageInfo,
+    pub captured: Option<NaiveDateTime>,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ImageSelection {
+    Index(usize),
+    All,
+}
+
+impl Source {
+    pub fn read(item: IngestItem, images: ImageSelection, mode: Mode) -> Result<Vec<Self>, Error> {
+        let IngestItem {
+            input_path,
+            output_prefix,
+        } = item;
+
+        let input = map_err!(
+            OpenOptions::new().read(true).write(false).open(&input_path),
+            Error::Io,
+            "Couldn't open input RAW file",
+        )?;
+
+        let modified = input.metadata().and_then(|md| md.modified()).ok();
+        let mut raw_file = RawFile::new(input_path.as_path(), BufReader::new(input));
+
+        if mode == Mode::Copy {
+            let source = Self::read_copy(input_path, output_prefix, &mut raw_file, modified);
+            return Ok(vec![source]);
+        }
+
+        let decoder = map_err!(
+            get_decoder(&mut raw_file),
+            Error::ImgOp,
+            "no compatible RAW image decoder available",
+        )?;
+
+        let count = map_err!(
+            decoder.raw_image_count(),
+            Error::ImgOp,
+            "couldn't count the images in RAW file",
+        )?;
+
+        let indices = match images {
+            ImageSelection::All => 0..count,
+            ImageSelection::Index(index) if index < count => index..index + 1,
+            ImageSelection::Index(index) => {
+                return Err(Error::NotFound(format!(
+                    "image index {index} not found, file contains {count} image(s)"
+                )))
+            }
+        };
+
+        indices
+            .map(|index| {
+                let params = RawDecodeParams { image_index: index };
+
+                let md = map_err!(
+                    decoder.raw_metadata(&mut raw_file, params.clone()),
+                    Error::ImgOp,
+                    "couldn't extract image metadata",
+                )?;
+
+                // a dummy decode only reads the image headers, not the pixel data
+                let image = map_err!(
+                    decoder.raw_image(&mut raw_file, params, true),
+                    Error::ImgOp,
+                    "couldn't read image dimensions",
+                )?;
+
+                Ok(Self {
+                    input_path: input_path.clone(),
+                    output_prefix: output_prefix.clone(),
+                    captured: capture_time(&md),
+                    image: ImageInfo {
+                        index,
+                        ..(&image).into()
+                    },
+                    md,
+                })
+            })
+            .collect()
+    }
+
+    /// Copies don't need a decodable image, so whatever rawler can't read is left empty, and the
+    /// capture time falls back to the file's modification time
+    fn read_copy(
+        input_path: PathBuf,
+        output_prefix: PathBuf,
+        raw_file: &mut RawFile,
+        modified: Option<SystemTime>,
+    ) -> Self {
+        let params = RawDecodeParams::default();
+        let decoder = get_decoder(raw_file).ok();
+
+        let mut md = decoder
+            .as_ref()
+            .and_then(|decoder| decoder.raw_metadata(raw_file, params.clone()).ok())
+            .unwrap_or_else(|| RawMetadata {
+                exif: Exif::default(),
+                model: String::new(),
+                make: String::new(),
+                lens: None,
+                unique_image_id: None,
+                rating: None,
+            });
+
+        let image = decoder
+            .as_ref()
+            .and_then(|decoder| decoder.raw_image(raw_file, params, true).ok())
+            .map_or_else(ImageInfo::default, |image| (&image).into());
+
+        if let (None, Some(modified)) = (&md.exif.date_time_original, modified) {
+            set_capture_time(&mut md, DateTime::<Local>::from(modified).naive_local());
+        }
+
+        Self {
+            input_path,
+            output_prefix,
+            captured: capture_time(&md),
+            image,
+            md,
+        }
+    }
+
+    /// Images without a capture time are placed last
+    fn capture_order(&self, other: &Self) -> Ordering {
+        fn key(source: &Source) -> (bool, Option<NaiveDateTime>, &Path, usize) {
+            (
+                source.captured.is_none(),
+                source.captured,
+                &source.input_path,
+                source.image.index,
+            )
+        }
+
+        key(self).cmp(&key(other))
+    }
+
+    pub fn original_filename(&self) -> Cow<'_, str> {
+        self.input_path
+            .file_stem()
+            .unwrap_or_else(|| {
+                panic!(
+                    "couldn't deduce filename from {}",
+                    self.input_path.display()
+                )
+            })
+            .to_string_lossy()
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct Planned {
+    pub source: Source,
+    pub output_path: PathBuf,
+    /// whether an existing file at `output_path` may be replaced
+    pub overwrite: bool,
+    pub backup_paths: Vec<PathBuf>,
+}
+
+impl Planned {
+    /// The output path, followed by every backup path
+    pub fn paths(&self) -> impl Iterator<Item = &PathBuf> {
+        iter::once(&self.output_path).chain(&self.backup_paths)
+    }
+
+    fn existing(&self, vacated: &HashSet<PathBuf>) -> Option<&PathBuf> {
+        self.paths().find(|path| occupied(path, vacated))
+    }
+
+    fn suffixed(&self, suffix: &str) -> Vec<PathBuf> {
+        self.paths().map(|path| with_suffix(path, suffix)).collect()
+    }
+
+    fn set_paths(&mut self, mut paths: Vec<PathBuf>) {
+        self.output_path = paths.remove(0);
+        self.backup_paths = paths;
+    }
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, ValueEnum)]
+pub enum Collision {
+    /// keep whatever got the name first
+    Skip,
+    /// replace existing files; within a run, the image captured last wins
+    Overwrite,
+    /// number colliding outputs, i.e. `name_1.dng`, `name_2.dng`, ...
+    Suffix,
+    /// append a hash of the source file to colliding outputs, skipping sources that are identical
+    HashSuffix,
+    /// fail every output that would replace another
+    #[default]
+    Error,
+}
+
+#[derive(Debug)]
+pub enum Conflict {
+    Skipped(Planned, String),
+    /// the output is already on disk, i.e. from an earlier import of the same files
+    Exists(Planned, String),
+    Failed(Planned, Error),
+}
+
+impl Conflict {
+    pub const fn planned(&self) -> &Planned {
+        match self {
+            Self::Skipped(planned, _) | Self::Exists(planned, _) | Self::Failed(planned, _) => {
+                planned
+            }
+        }
+    }
+}
+
+/// Orders `sources` by capture time, breaking ties by input path so that repeated runs over the
+/// same files produce the same names
+pub fn plan(
+    mut sources: Vec<Source>,
+    output_dir: &Path,
+    format: &FilenameFormat,
+    mode: Mode,
+) -> Vec<Planned> {
+    sources.sort_by(Source::capture_order);
+
+    let counters = counters(&sources);
+    let mut per_dir = HashMap::new();
+
+    sources
+        .into_iter()
+        .zip(counters)
+        .map(|(source, counters)| {
+            let mut ctx = RenderContext {
+                original_filename: &source.original_filename(),
+                md: &source.md,
+                image: source.image,
+                counters,
+            };
+
+            let dir = output_dir
+                .join(&source.output_prefix)
+                .join(format.render_dir(&ctx));
+
+            ctx.counters.dir = next(&mut per_dir, dir.clone());
+
+            let extension = mode.extension(&source.input_path);
+            let output_path = dir.join(format.render_filename(&ctx, &extension));
+
+            Planned {
+                source,
+                output_path,
+                overwrite: false,
+                backup_paths: vec![],
+            }
+        })
+        .collect()
+}
+
+/// Counts `sources` in capture order, leaving them in the order they're given; `{seq.dir}` stays
+/// at 0, since it depends on the rendered output directory
+pub fn counters(sources: &[Source]) -> Vec<Counters> {
+    let mut order = (0..sources.len()).collect::<Vec<_>>();
+    order.sort_by(|&a, &b| sources[a].capture_order(&sources[b]));
+
+    let mut counters = vec![Counters::default(); sources.len()];
+    let mut per_day = HashMap::new();
+
+    for (i, run) in order.into_iter().zip(1..) {
+        counters[i] = Counters {
+            run,
+            day: next(&mut per_day, sources[i].captured.map(|dt| dt.date())),
+            dir: 0,
+        };
+    }
+
+    counters
+}
+
+fn next<K: Eq + Hash>(counts: &mut HashMap<K, usize>, key: K) -> usize {
+    *counts.entry(key).and_modify(|n| *n += 1).or_insert(1)
+}
+
+/// Has to run before [`resolve_collisions`], so that backups are checked for collisions and
+/// renamed along with their outputs
+pub fn add_backups(planned: &mut [Planned], output_dir: &Path, backup_dirs: &[PathBuf]) {
+    for planned in planned {
+        let relative = planned
+            .output_path
+            .strip_prefix(output_dir)
+            .unwrap_or(&planned.output_path);
+
+        planned.backup_paths = backup_dirs.iter().map(|dir| dir.join(relative)).collect();
+    }
+}
+
+/// Runs before any job is started, so that parallel jobs never race for the same path
+pub fn resolve_collisions(
+    planned: Vec<Planned>,
+    strategy: Collision,
+) -> (Vec<Planned>, Vec<Conflict>) {
+    resolve(planned, strategy, &HashSet::new())
+}
+
+/// Like [`resolve_collisions`], except that a path is free if the file that's there is renamed
+/// away in the same batch
+pub fn resolve_renames(planned: &[Planned], strategy: Collision) -> (Vec<Planned>, Vec<Conflict>) {
+    let mut vacated = planned
+        .iter()
+        .map(|planned| planned.source.input_path.clone())
+        .collect::<HashSet<_>>();
+
+    loop {
+        let (resolved, conflicts) = resolve(planned.to_vec(), strategy, &vacated);
+
+        // a file that isn't renamed stays in the way
+        let stays = conflicts
+            .iter()
+            .map(|conflict| &conflict.planned().source.input_path)
+            .filter(|path| vacated.contains(*path))
+            .cloned()
+            .collect::<Vec<_>>();
+
+        if stays.is_empty() {
+            return (resolved, conflicts);
+        }
+
+        for path in &stays {
+            vacated.remove(path);
+        }
+    }
+}
+
+/// Every file comes after the one whose path it takes over. A cycle is broken by moving its first
+/// file aside, which is listed with `true`, before the rest of it.
+pub fn rename_order(planned: &[Planned]) -> Vec<(usize, bool)> {
+    let by_input = planned
+        .iter()
+        .enumerate()
+        .map(|(index, planned)| (&planned.source.input_path, index))
+        .collect::<HashMap<_, _>>();
+
+    let mut placed = vec![false; planned.len()];
+    let mut order = vec![];
+
+    for start in 0..planned.len() {
+        let mut chain = vec![];
+        let mut index = start;
+
+        let cycle = loop {
+            if placed[index] {
+                break false;
+            } else if chain.contains(&index) {
+                break true;
+            }
+
+            chain.push(index);
+
+            match by_input.get(&planned[index].output_path) {
+                Some(&next) => index = next,
+                None => break false,
+            }
+        };
+
+        if cycle {
+            order.push((start, true));
+        }
+
+        for &index in chain.iter().rev() {
+            placed[index] = true;
+            order.push((index, false));
+        }
+    }
+
+    order
+}
+
+fn resolve(
+    planned: Vec<Planned>,
+    strategy: Collision,
+    vacated: &HashSet<PathBuf>,
+) -> (Vec<Planned>, Vec<Conflict>) {
+    let mut groups = Vec::<Vec<Planned>>::new();
+    let mut group_of = HashMap::new();
+
+    for planned in planned {
+        let group = *group_of
+            .entry(planned.output_path.clone())
+            .or_insert_with(|| {
+                groups.push(vec![]);
+                groups.len() - 1
+            });
+
+        groups[group].push(planned);
+    }
+
+    let mut taken = groups
+        .iter()
+        .flatten()
+        .flat_map(Planned::paths)
+        .cloned()
+        .collect::<HashSet<_>>();
+    let mut resolved = vec![];
+    let mut conflicts = vec![];
+
+    for group in groups {
+        let on_disk = group[0].existing(vacated).is_some();
+
+        if group.len() == 1 && !on_disk {
+            resolved.extend(group);
+            continue;
+        }
+
+        let dir = group[0].paths().find(|path| path.is_dir()).cloned();
+
+        if let Some(dir) = dir {
+            conflicts.extend(group.into_iter().map(|planned| {
+                let msg = format!(
+                    "computed filepath already exists as a directory: {}",
+                    dir.display()
+                );
+
+                Conflict::Failed(planned, Error::AlreadyExists(msg))
+            }));
+
+            continue;
+        }
+
+        resolve_group(
+            group,
+            strategy,
+            on_disk,
+            vacated,
+            &mut taken,
+            &mut resolved,
+            &mut conflicts,
+        );
+    }
+
+    resolved.sort_by(|a, b| a.source.capture_order(&b.source));
+
+    (resolved, conflicts)
+}
+
+fn resolve_group(
+    group: Vec<Planned>,
+    strategy: Collision,
+    on_disk: bool,
+    vacated: &HashSet<PathBuf>,
+    taken: &mut HashSet<PathBuf>,
+    resolved: &mut Vec<Planned>,
+    conflicts: &mut Vec<Conflict>,
+) {
+    let mut group = group.into_iter();
+
+    match strategy {
+        Collision::Error | Collision::Skip => {
+            if on_disk {
+                conflicts.extend(group.next().map(|planned| {
+                    let path = planned.existing(vacated).unwrap_or(&planned.output_path);
+                    let msg = format!("already exists: {}", path.display());
+
+                    Conflict::Exists(planned, msg)
+                }));
+            } else {
+                resolved.extend(group.next());
+            }
+
+            conflicts.extend(group.map(|planned| {
+                let path = planned.existing(vacated).unwrap_or(&planned.output_path);
+                let msg = format!("won't overwrite existing file: {}", path.display());
+
+                if strategy == Collision::Skip {
+                    Conflict::Skipped(planned, msg)
+                } else {
+                    Conflict::Failed(planned, Error::AlreadyExists(msg))
+                }
+            }));
+        }
+
+        Collision::Overwrite => {
+            let mut group = group.collect::<Vec<_>>();
+            let mut last = group.pop().unwrap();
+
+            last.overwrite = on_disk;
+
+            conflicts.extend(group.into_iter().map(|planned| {
+                let msg = format!(
+                    "superseded by \"{}\" which was captured later",
+                    last.source.input_path.display()
+                );
+
+                Conflict::Skipped(planned, msg)
+            }));
+
+            resolved.push(last);
+        }
+
+        Collision::Suffix => {
+            if !on_disk {
+                resolved.extend(group.next());
+            }
+
+            let mut n = 0;
+            for mut planned in group {
+                let paths = loop {
+                    n += 1;
+
+                    let candidates = planned.suffixed(&n.to_string());
+                    if candidates
+                        .iter()
+                        .all(|path| !taken.contains(path) && !occupied(path, vacated))
+                    {
+                        break candidates;
+                    }
+                };
+
+                taken.extend(paths.iter().cloned());
+                planned.set_paths(paths);
+                resolved.push(planned);
+            }
+        }
+
+        Collision::HashSuffix => {
+            for mut planned in group {
+                let hash = match sha256_file(&planned.source.input_path) {
+                    Ok(hash) => hash,
+                    Err(e) => {
+                        let err = Error::Io("couldn't hash source file".into(), e);
+                        conflicts.push(Conflict::Failed(planned, err));
+                        continue;
+                    }
+                };
+
+                // images from the same file share a hash, so they're told apart by their index
+                let suffix = match planned.source.image.index {
+                    0 => hash[..8].to_string(),
+                    index => format!("{}-{index}", &hash[..8]),
+                };
+
+                let paths = planned.suffixed(&suffix);
+                let free = paths
+                    .iter()
+                    .all(|path| !taken.contains(path) && !occupied(path, vacated));
+
+                taken.extend(paths.iter().cloned());
+                planned.set_paths(paths);
+
+                if free {
+                    resolved.push(planned);
+                } else {
+                    let msg = format!(
+                        "an identical image was already written to {}",
+                        planned.output_path.display()
+                    );
+
+                    conflicts.push(Conflict::Skipped(planned, msg));
+                }
+            }
+        }
+    }
+}
+
+fn occupied(path: &Path, vacated: &HashSet<PathBuf>) -> bool {
+    !vacated.contains(path) && path.exists()
+}
+
+fn with_suffix(path: &Path, suffix: &str) -> PathBuf {
+    let stem = path.file_stem().unwrap_or_default().to_string_lossy();
+
+    let filename = path.extension().map_or_else(
+        || format!("{stem}_{suffix}"),
+        |ext| format!("{stem}_{suffix}.{}", ext.to_string_lossy()),
+    );
+
+    path.with_file_name(filename)
+}
+
+#[cfg(test)]
+mod test_plan {
+    use std::path::{Path, PathBuf};
+
+    use rawler::exif::Exif;
+    use tempfile::tempdir;
+
+    use super::{
+        add_backups, counters, plan, rename_order, resolve_collisions, resolve_renames, Collision,
+        Conflict, Planned, Source,
+    };
+    use crate::{fixtures, job::Mode, parse::FilenameFormat};
+
+    fn source(name: &str, prefix: &str, date: Option<&str>) -> Source {
+        let md = fixtures::metadata(
+            "Fujifilm",
+            "X-T5",
+            Exif {
+                date_time_original: date.map(Into::into),
+                ..Default::default()
+            },
+        );
+
+        Source {
+            output_prefix: prefix.into(),
+            ..fixtures::source(format!("{name}.RAF"), md)
+        }
+    }
+
+    fn names(fmt: &str, sources: Vec<Source>) -> Vec<String> {
+        let format = FilenameFormat::parse(fmt).unwrap();
+
+        plan(sources, Path::new("out"), &format, Mode::Convert)
+            .into_iter()
+            .map(|planned| {
+                let relative = planned.output_path.strip_prefix("out").unwrap();
+                relative.to_string_lossy().into_owned()
+            })
+            .collect()
+    }
+
+    #[test]
+    fn numbers_in_capture_order() {
+        let sources = vec![
+            source("DSCF0003", "", Some("2024:05:01 12:00:02")),
+            source("DSCF0001", "", None),
+            source("DSCF0002", "", Some("2024:05:01 12:00:01")),
+        ];
+
+        assert_eq!(
+            names("{seq:04}_", sources),
+            [
+                "0001_DSCF0002.dng",
+                "0002_DSCF0003.dng",
+                "0003_DSCF0001.dng"
+            ]
+        );
+    }
+
+    #[test]
+    fn counts_in_capture_order_without_reordering() {
+        let sources = [
+            source("DSCF0001", "", None),
+            source("DSCF0002", "", Some("2024:05:02 12:00:00")),
+            source("DSCF0003", "", Some("2024:05:01 12:00:00")),
+        ];
+
+        let counters = counters(&sources)
+            .into_iter()
+            .map(|counters| (counters.run, counters.day))
+            .collect::<Vec<_>>();
+
+        assert_eq!(counters, [(3, 1), (2, 1), (1, 1)]);
+    }
+
+    #[test]
+    fn orders_images_of_one_file_by_index() {
+        let mut first = source("DSCF0001", "", Some("2024:05:01 12:00:00"));
+        let mut second = first.clone();
+        first.image.index = 1;
+        second.image.index = 0;
+
+        assert_eq!(
+            names("{seq}_{image.index}_", vec![first, second]),
+            ["1_0_DSCF0001.dng", "2_1_DSCF0001.dng"]
+        );
+    }
+
+    #[test]
+    fn copies_keep_original_extension() {
+        let format = FilenameFormat::parse("%Y/{camera.model}_").unwrap();
+        let sources = vec![source("DSCF0001", "", Some("2024:05:01 12:00:00"))];
+
+        let planned = plan(sources, Path::new("out"), &format, Mode::Copy);
+
+        assert_eq!(
+            planned[0].output_path,
+            Path::new("out/2024/X-T5_DSCF0001.RAF")
+        );
+    }
+
+    #[test]
+    fn day_counter_restarts_per_capture_date() {
+        let sources = vec![
+            source("A", "", Some("2024:05:01 12:00:00")),
+            source("B", "", Some("2024:05:02 09:00:00")),
+            source("C", "", Some("2024:05:01 13:00:00")),
+            source("D", "", Some("2024:05:02 10:00:00")),
+        ];
+
+        assert_eq!(
+            names("%d_{seq.day:02}_{seq}_", sources),
+            [
+                "01_01_1_A.dng",
+                "01_02_2_C.dng",
+                "02_01_3_B.dng",
+                "02_02_4_D.dng"
+            ]
+        );
+    }
+
+    #[test]
+    fn dir_counter_restarts_per_output_dir() {
+        let sources = vec![
+            source("A", "day1", Some("2024:05:01 12:00:00")),
+            source("B", "day2", Some("2024:05:01 12:00:01")),
+            source("C", "day1", Some("2024:05:01 12:00:02")),
+        ];
+
+        let expected = [
+            Path::new("day1").join("1_A.dng"),
+            Path::new("day2").join("1_B.dng"),
+            Path::new("day1").join("2_C.dng"),
+        ];
+
+        assert_eq!(
+            names("{seq.dir}_", sources),
+            expected.map(|path| path.to_string_lossy().into_owned())
+        );
+    }
+
+    #[test]
+    fn renders_dirs_inside_recurse_prefix() {
+        let sources = vec![
+            source("A", "card1", Some("2024:05:01 12:00:00")),
+            source("B", "card1", Some("2024:05:02 12:00:00")),
+            source("C", "card2", Some("2024:05:02 12:00:01")),
+            source("D", "", None),
+        ];
+
+        let expected = [
+            Path::new("card1/2024/05-01/X-T5/1_A.dng"),
+            Path::new("card1/2024/05-02/X-T5/1_B.dng"),
+            Path::new("card2/2024/05-02/X-T5/1_C.dng"),
+            Path::new("undated/X-T5/1_D.dng"),
+        ];
+
+        assert_eq!(
+            names("{%Y|undated}/{%m-%d?}/{camera.model}/{seq.dir}_", sources),
+            expected.map(|path| path
+                .iter()
+                .collect::<PathBuf>()
+                .to_string_lossy()
+                .into_owned())
+        );
+    }
+
+    fn colliding(dir: &Path, names: &[&str]) -> Vec<Planned> {
+        names
+            .iter()
+            .enumerate()
+            .map(|(i, name)| {
+                let date = format!("2024:05:01 12:00:{i:02}");
+                let mut source = source(name, "", Some(&date));
+                source.input_path = dir.join(format!("{name}.RAF"));
+
+                Planned {
+                    source,
+                    output_path: dir.join("out.dng"),
+                    overwrite: false,
+                    backup_paths: vec![],
+                }
+            })
+            .collect()
+    }
+
+    fn renames(dir: &Path, names: &[(&str, &str)]) -> Vec<Planned> {
+        names
+            .iter()
+            .enumerate()
+            .map(|(i, (from, to))| {
+                let date = format!("2024:05:01 12:00:{i:02}");
+                let mut source = source(from, "", Some(&date));
+                source.input_path = dir.join(format!("{from}.RAF"));
+                std::fs::write(&source.input_path, from).unwrap();
+
+                Planned {
+                    source,
+                    output_path: dir.join(format!("{to}.RAF")),
+                    overwrite: false,
+                    backup_paths: vec![],
+                }
+            })
+            .collect()
+    }
+
+    fn outputs(planned: &[Planned]) -> Vec<String> {
+        planned
+            .iter()
+            .map(|p| {
+                p.output_path
+                    .file_name()
+                    .unwrap()
+                    .to_string_lossy()
+                    .into_owned()
+            })
+            .collect()
+    }
+
+    #[test]
+    fn error_and_skip_keep_first_capture() {
+        let dir = tempdir().unwrap();
+
+        for strategy in [Collision::Error, Collision::Skip] {
+            let (resolved, conflicts) =
+                resolve_collisions(colliding(dir.path(), &["A", "B"]), strategy);
+
+            assert_eq!(resolved.len(), 1);
+            assert!(resolved[0].source.input_path.ends_with("A.RAF"));
+            assert_eq!(conflicts.len(), 1);
+            assert_eq!(
+                matches!(conflicts[0], Conflict::Skipped(..)),
+                strategy == Collision::Skip
+            );
+        }
+    }
+
+    #[test]
+    fn existing_file_is_never_replaced_without_overwrite() {
+        let dir = tempdir().unwrap();
+        std::fs::write(dir.path().join("out.dng"), b"").unwrap();
+
+        let (resolved, conflicts) =
+            resolve_collisions(colliding(dir.path(), &["A"]), Collision::Skip);
+        assert!(resolved.is_empty());
+        assert_eq!(conflicts.len(), 1);
+
+        let (resolved, _) = resolve_collisions(colliding(dir.path(), &["A"]), Collision::Overwrite);
+        assert!(resolved[0].overwrite);
+    }
+
+    #[test]
+    fn existing_outputs_are_told_apart_from_collisions() {
+        let dir = tempdir().unwrap();
+        std::fs::write(dir.path().join("out.dng"), b"").unwrap();
+
+        let (resolved, conflicts) =
+            resolve_collisions(colliding(dir.path(), &["A", "B"]), Collision::Error);
+
+        assert!(resolved.is_empty());
+        assert!(
+            matches!(&conflicts[0], Conflict::Exists(p, _) if p.source.input_path.ends_with("A.RAF"))
+        );
+        assert!(
+            matches!(&conflicts[1], Conflict::Failed(p, _) if p.source.input_path.ends_with("B.RAF"))
+        );
+    }
+
+    #[test]
+    fn overwrite_keeps_last_capture() {
+        let dir = tempdir().unwrap();
+
+        let (resolved, conflicts) = resolve_collisions(
+            colliding(dir.path(), &["A", "B", "C"]),
+            Collision::Overwrite,
+        );
+
+        assert_eq!(resolved.len(), 1);
+        assert!(resolved[0].source.input_path.ends_with("C.RAF"));
+        assert!(!resolved[0].overwrite);
+        assert_eq!(conflicts.len(), 2);
+    }
+
+    #[test]
+    fn suffix_numbers_around_existing_files() {
+        let dir = tempdir().unwrap();
+        std::fs::write(dir.path().join("out.dng"), b"").unwrap();
+        std::fs::write(dir.path().join("out_2.dng"), b"").unwrap();
+
+        let (resolved, conflicts) =
+            resolve_collisions(colliding(dir.path(), &["A", "B"]), Collision::Suffix);
+
+        assert!(conflicts.is_empty());
+        assert_eq!(outputs(&resolved), ["out_1.dng", "out_3.dng"]);
+    }
+
+    #[test]
+    fn hash_suffix_skips_identical_sources() {
+        let dir = tempdir().unwrap();
+        std::fs::write(dir.path().join("A.RAF"), b"same").unwrap();
+        std::fs::write(dir.path().join("B.RAF"), b"same").unwrap();
+        std::fs::write(dir.path().join("C.RAF"), b"different").unwrap();
+
+        let (resolved, conflicts) = resolve_collisions(
+            colliding(dir.path(), &["A", "B", "C"]),
+            Collision::HashSuffix,
+        );
+
+        assert_eq!(resolved.len(), 2);
+        assert_eq!(conflicts.len(), 1);
+        assert!(
+            matches!(&conflicts[0], Conflict::Skipped(p, _) if p.source.input_path.ends_with("B.RAF"))
+        );
+
+        let names = outputs(&resolved);
+        assert_ne!(names[0], names[1]);
+        assert!(names
+            .iter()
+            .all(|name| name.starts_with("out_") && name.len() == "out_.dng".len() + 8));
+    }
+
+    #[test]
+    fn backups_mirror_renamed_outputs() {
+        let dir = tempdir().unwrap();
+        let mut planned = colliding(dir.path(), &["A", "B"]);
+        add_backups(
+            &mut planned,
+            dir.path(),
+            &["/mnt/ssd".into(), "backup".into()],
+        );
+
+        let (resolved, _) = resolve_collisions(planned, Collision::Suffix);
+
+        assert_eq!(
+            resolved[1].backup_paths,
+            [
+                Path::new("/mnt/ssd/out_1.dng"),
+                Path::new("backup/out_1.dng")
+            ]
+        );
+    }
+
+    #[test]
+    fn existing_backups_collide_like_outputs() {
+        let dir = tempdir().unwrap();
+        let (out, backup) = (dir.path().join("out"), dir.path().join("backup"));
+        std::fs::create_dir_all(&backup).unwrap();
+        std::fs::write(backup.join("out.dng"), b"").unwrap();
+        std::fs::write(backup.join("out_1.dng"), b"").unwrap();
+
+        let planned = |names| {
+            let mut planned = colliding(&out, names);
+            add_backups(&mut planned, &out, std::slice::from_ref(&backup));
+            planned
+        };
+
+        let (resolved, conflicts) = resolve_collisions(planned(&["A"]), Collision::Error);
+        assert!(resolved.is_empty());
+        assert!(
+            matches!(&conflicts[0], Conflict::Exists(_, msg) if msg.contains("backup/out.dng"))
+        );
+
+        let (resolved, _) = resolve_collisions(planned(&["A"]), Collision::Suffix);
+        assert_eq!(resolved[0].output_path, out.join("out_2.dng"));
+        assert_eq!(resolved[0].backup_paths, [backup.join("out_2.dng")]);
+    }
+
+    #[test]
+    fn renames_into_paths_that_are_renamed_away() {
+        let dir = tempdir().unwrap();
+        let planned = renames(dir.path(), &[("a", "b"), ("b", "c")]);
+
+        let (resolved, conflicts) = resolve_renames(&planned, Collision::Error);
+
+        assert!(conflicts.is_empty());
+        assert_eq!(outputs(&resolved), ["b.RAF", "c.RAF"]);
+        assert_eq!(rename_order(&resolved), [(1, false), (0, false)]);
+    }
+
+    #[test]
+    fn files_that_arent_renamed_stay_in_the_way() {
+        let dir = tempdir().unwrap();
+        std::fs::write(dir.path().join("c.RAF"), b"").unwrap();
+        let planned = renames(dir.path(), &[("a", "b"), ("b", "c")]);
+
+        let (resolved, conflicts) = resolve_renames(&planned, Collision::Skip);
+
+        assert!(resolved.is_empty());
+        assert_eq!(conflicts.len(), 2);
+    }
+
+    #[test]
+    fn cycles_are_broken_by_moving_a_file_aside() {
+        let dir = tempdir().unwrap();
+        let planned = renames(dir.path(), &[("a", "b"), ("b", "a")]);
+
+        let (resolved, conflicts) = resolve_renames(&planned, Collision::Error);
+
+        assert!(conflicts.is_empty());
+        assert_eq!(rename_order(&resolved), [(0, true), (1, false), (0, false)]);
+    }
+}
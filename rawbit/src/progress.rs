@@ -0,0 +1,168 @@
+// Copyright (c) Carter J. Canedy <cartercanedy42@gmail.com>
+// rawbit is free software, distributable under the terms of the MIT license
+// See https://raw.githubusercontent.com/cartercanedy/rawbit/refs/heads/master/LICENSE.txt
+
+use std::{
+    io::{self, IsTerminal as _, Write as _},
+    sync::{Mutex, MutexGuard, PoisonError},
+    time::{Duration, Instant},
+};
+
+use crate::events;
+
+const WIDTH: usize = 30;
+
+/// Moves the cursor to the start of the line and clears it
+const CLEAR_LINE: &str = "\r\x1b[2K";
+
+static BAR: Mutex<Option<Bar>> = Mutex::new(None);
+
+/// Log messages are printed through [`suspend`], which clears the bar first, so that they scroll
+/// by above it
+#[derive(Debug)]
+struct Bar {
+    total: usize,
+    done: usize,
+    read: u64,
+    written: u64,
+    started: Instant,
+}
+
+impl Bar {
+    fn render(&self, elapsed: Duration) -> String {
+        let filled = (WIDTH * self.done).checked_div(self.total).unwrap_or(WIDTH);
+        let elapsed_ms = elapsed.as_millis().max(1);
+
+        let eta = if self.done == 0 {
+            "--".into()
+        } else {
+            let remaining = (self.total - self.done) as u128;
+            duration(elapsed_ms * remaining / self.done as u128 / 1000)
+        };
+
+        format!(
+            "[{:<WIDTH$}] {}/{}  {} read  {} written  ETA {eta}",
+            "=".repeat(filled),
+            self.done,
+            self.total,
+            rate(self.read, elapsed_ms),
+            rate(self.written, elapsed_ms),
+        )
+    }
+
+    fn draw(&self) {
+        let mut stdout = io::stdout().lock();
+        let _ = write!(
+            stdout,
+            "{CLEAR_LINE}{}",
+            self.render(self.started.elapsed())
+        );
+        let _ = stdout.flush();
+    }
+}
+
+/// In MB/s with one decimal
+fn rate(bytes: u64, elapsed_ms: u128) -> String {
+    let tenths = u128::from(bytes) * 1000 / elapsed_ms / 100_000;
+    format!("{}.{} MB/s", tenths / 10, tenths % 10)
+}
+
+fn duration(secs: u128) -> String {
+    match secs {
+        0..60 => format!("{secs}s"),
+        60..3600 => format!("{}m{:02}s", secs / 60, secs % 60),
+        _ => format!("{}h{:02}m", secs / 3600, secs / 60 % 60),
+    }
+}
+
+fn bar() -> MutexGuard<'static, Option<Bar>> {
+    BAR.lock().unwrap_or_else(PoisonError::into_inner)
+}
+
+pub fn wanted(quiet: bool) -> bool {
+    !quiet && !events::enabled() && io::stdout().is_terminal()
+}
+
+pub fn start(total: usize) {
+    bar()
+        .insert(Bar {
+            total,
+            done: 0,
+            read: 0,
+            written: 0,
+            started: Instant::now(),
+        })
+        .draw();
+}
+
+/// Whether it's worth measuring what's read and written
+pub fn active() -> bool {
+    bar().is_some()
+}
+
+pub fn advance(read: u64, written: u64) {
+    if let Some(bar) = bar().as_mut() {
+        bar.done += 1;
+        bar.read += read;
+        bar.written += written;
+        bar.draw();
+    }
+}
+
+pub fn finish() {
+    if bar().take().is_some() {
+        print!("{CLEAR_LINE}");
+        let _ = io::stdout().flush();
+    }
+}
+
+/// Clears the progress bar while `f` runs, i.e. while it logs something
+// the lock is held on purpose, so that other jobs can't redraw the bar while `f` runs
+#[allow(clippy::significant_drop_tightening)]
+pub fn suspend<T>(f: impl FnOnce() -> T) -> T {
+    let bar = bar();
+
+    if bar.is_some() {
+        print!("{CLEAR_LINE}");
+    }
+
+    let result = f();
+
+    if let Some(bar) = bar.as_ref() {
+        bar.draw();
+    }
+
+    result
+}
+
+#[cfg(test)]
+mod test_progress {
+    use std::time::{Duration, Instant};
+
+    use super::{duration, rate, Bar};
+
+    #[test]
+    fn renders_counts_throughput_and_eta() {
+        let bar = Bar {
+            total: 2000,
+            done: 500,
+            read: 12_500_000_000,
+            written: 25_000_000_000,
+            started: Instant::now(),
+        };
+
+        let rendered = bar.render(Duration::from_secs(100));
+
+        assert!(rendered.starts_with(&format!("[{:<30}] 500/2000", "=".repeat(7))));
+        assert!(rendered.contains("125.0 MB/s read  250.0 MB/s written"));
+        assert!(rendered.ends_with("ETA 5m00s"));
+    }
+
+    #[test]
+    fn formats_rates_and_durations() {
+        assert_eq!(rate(1_550_000, 1000), "1.5 MB/s");
+        assert_eq!(rate(0, 1), "0.0 MB/s");
+        assert_eq!(duration(59), "59s");
+        assert_eq!(duration(3 * 3600 + 5 * 60), "3h05m");
+    }
+}
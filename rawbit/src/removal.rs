@@ -0,0 +1,211 @@
+// Copyright (c) Carter J. Canedy <cartercanedy42@gmail.com>
+// rawbit is free software, distributable under the terms of the MIT license
+// See https://raw.githubusercontent.com/cartercanedy/rawbit/refs/heads/master/LICENSE.txt
+
+use std::{
+    collections::HashMap,
+    fs::{canonicalize, remove_file},
+    io,
+    path::{Path, PathBuf},
+};
+
+use crate::{args::IngestItem, common::sync_dir, job::Error, plan::Planned};
+
+#[derive(Debug)]
+pub enum Removal {
+    Deleted,
+    Kept(String),
+    /// deleting the file failed
+    Failed(io::Error),
+}
+
+#[derive(Debug, Default)]
+struct Tally {
+    written: usize,
+    /// the first reason not to delete the file
+    blocked: Option<String>,
+}
+
+/// A file is only deleted once each of its outputs, i.e. one per image with `--all-images`, has
+/// been written and verified
+#[derive(Debug)]
+pub struct Removals {
+    /// in ingest order, so that decisions are reported in a stable order
+    order: Vec<PathBuf>,
+    tallies: HashMap<PathBuf, Tally>,
+}
+
+impl Removals {
+    pub fn new(ingest: &[IngestItem]) -> Self {
+        let order = ingest
+            .iter()
+            .map(|item| item.input_path.clone())
+            .collect::<Vec<_>>();
+
+        let tallies = order
+            .iter()
+            .map(|path| (path.clone(), Tally::default()))
+            .collect();
+
+        Self { order, tallies }
+    }
+
+    pub fn written(&mut self, input_path: &Path, output_path: &Path) {
+        let replaced_source = matches!(
+            (canonicalize(input_path), canonicalize(output_path)),
+            (Ok(input), Ok(output)) if input == output
+        );
+
+        if replaced_source {
+            self.blocked(input_path, "the file was replaced by its own output".into());
+        }
+
+        self.tally(input_path).written += 1;
+    }
+
+    pub fn blocked(&mut self, input_path: &Path, reason: String) {
+        self.tally(input_path).blocked.get_or_insert(reason);
+    }
+
+    /// Images without a result were never started
+    pub fn finished<T>(&mut self, planned: &[Planned], results: &[Result<T, Error>]) {
+        for (planned, result) in planned.iter().zip(results) {
+            match result {
+                Ok(_) => {
+                    for output_path in planned.paths() {
+                        self.written(&planned.source.input_path, output_path);
+                    }
+                }
+                Err(err) => self.blocked(&planned.source.input_path, err.message().into()),
+            }
+        }
+
+        for planned in planned.iter().skip(results.len()) {
+            self.blocked(
+                &planned.source.input_path,
+                "the run was stopped before it was imported".into(),
+            );
+        }
+    }
+
+    fn tally(&mut self, input_path: &Path) -> &mut Tally {
+        self.tallies.entry(input_path.to_path_buf()).or_default()
+    }
+
+    pub fn apply(mut self) -> Vec<(PathBuf, Removal)> {
+        self.order
+            .into_iter()
+            .map(|input_path| {
+                let removal = match self.tallies.remove(&input_path) {
+                    Some(Tally {
+                        blocked: Some(reason),
+                        ..
+                    }) => Removal::Kept(reason),
+
+                    Some(Tally { written: 0, .. }) | None => {
+                        Removal::Kept("no output was written".into())
+                    }
+
+                    Some(_) => match remove(&input_path) {
+                        Ok(()) => Removal::Deleted,
+                        Err(err) => Removal::Failed(err),
+                    },
+                };
+
+                (input_path, removal)
+            })
+            .collect()
+    }
+}
+
+fn remove(path: &Path) -> io::Result<()> {
+    remove_file(path)?;
+
+    match path.parent() {
+        Some(dir) if !dir.as_os_str().is_empty() => sync_dir(dir),
+        _ => sync_dir(Path::new(".")),
+    }
+}
+
+#[cfg(test)]
+mod test_removal {
+    use std::fs::write;
+
+    use tempfile::tempdir;
+
+    use rawler::exif::Exif;
+
+    use super::{Removal, Removals};
+    use crate::{args::IngestItem, fixtures, job::Error, plan::Planned};
+
+    #[test]
+    fn only_deletes_fully_written_sources() {
+        let dir = tempdir().unwrap();
+        let [written, partial, failed, unread] =
+            ["A.RAF", "B.RAF", "C.RAF", "D.RAF"].map(|name| dir.path().join(name));
+
+        let ingest = [&written, &partial, &failed, &unread]
+            .map(|path| {
+                write(path, b"").unwrap();
+                IngestItem::from((path, ""))
+            })
+            .to_vec();
+
+        let mut removals = Removals::new(&ingest);
+        removals.written(&written, &dir.path().join("out/A.dng"));
+        removals.written(&partial, &dir.path().join("out/B_0.dng"));
+        removals.blocked(&partial, "couldn't convert image to DNG".into());
+        removals.blocked(&failed, "won't overwrite existing file".into());
+
+        let decisions = removals.apply();
+
+        assert!(matches!(decisions[0].1, Removal::Deleted));
+        assert!(matches!(&decisions[1].1, Removal::Kept(reason) if reason.starts_with("couldn't")));
+        assert!(matches!(&decisions[2].1, Removal::Kept(reason) if reason.starts_with("won't")));
+        assert!(matches!(&decisions[3].1, Removal::Kept(_)));
+
+        assert!(!written.exists());
+        assert!(partial.exists() && failed.exists() && unread.exists());
+    }
+
+    #[test]
+    fn keeps_source_replaced_by_its_output() {
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("A.RAF");
+        write(&path, b"").unwrap();
+
+        let mut removals = Removals::new(&[IngestItem::from((&path, ""))]);
+        removals.written(&path, &path);
+
+        assert!(matches!(removals.apply()[0].1, Removal::Kept(_)));
+        assert!(path.exists());
+    }
+
+    #[test]
+    fn keeps_sources_of_images_that_were_never_started() {
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("A.CR3");
+        write(&path, b"").unwrap();
+
+        let planned = [0, 1].map(|index| {
+            let mut source =
+                fixtures::source(&path, fixtures::metadata("Canon", "R5", Exif::default()));
+            source.image.index = index;
+
+            Planned {
+                source,
+                output_path: dir.path().join(format!("out/A_{index}.dng")),
+                overwrite: false,
+                backup_paths: vec![],
+            }
+        });
+
+        let mut removals = Removals::new(&[IngestItem::from((&path, ""))]);
+        removals.finished(&planned, &[Ok::<(), Error>(())]);
+
+        assert!(
+            matches!(&removals.apply()[0].1, Removal::Kept(reason) if reason.contains("stopped"))
+        );
+        assert!(path.exists());
+    }
+}
@@ -0,0 +1,164 @@
+// Copyright (c) Carter J. Canedy <cartercanedy42@gmail.com>
+// rawbit is free software, distributable under the terms of the MIT license
+// See https://raw.githubusercontent.com/cartercanedy/rawbit/refs/heads/master/LICENSE.txt
+
+use clap::ValueEnum;
+
+const REPLACEMENT: char = '_';
+const MAX_COMPONENT_LEN: usize = 255;
+
+const WINDOWS_RESERVED_CHARS: &[char] = &['<', '>', ':', '"', '/', '\\', '|', '?', '*'];
+
+const WINDOWS_RESERVED_NAMES: &[&str] = &[
+    "CON", "PRN", "AUX", "NUL", "COM1", "COM2", "COM3", "COM4", "COM5", "COM6", "COM7", "COM8",
+    "COM9", "LPT1", "LPT2", "LPT3", "LPT4", "LPT5", "LPT6", "LPT7", "LPT8", "LPT9",
+];
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, ValueEnum)]
+pub enum Profile {
+    /// only `/` and NUL are replaced, components are limited to 255 bytes
+    Posix,
+    /// reserved characters and device names (`CON`, `LPT1`, ...) are replaced, trailing dots and
+    /// spaces are removed, components are limited to 255 UTF-16 code units
+    Windows,
+    /// reserved characters are replaced, trailing dots and spaces are removed, components are
+    /// limited to 255 UTF-16 code units
+    Exfat,
+    /// valid on all of the above
+    Portable,
+}
+
+impl Profile {
+    /// The filesystem rawbit is most likely writing to
+    pub const fn native() -> Self {
+        if cfg!(windows) {
+            Self::Windows
+        } else {
+            Self::Posix
+        }
+    }
+
+    fn is_reserved_char(self, c: char) -> bool {
+        match self {
+            Self::Posix => c == '/' || c == '\0',
+            Self::Windows | Self::Exfat | Self::Portable => {
+                c.is_control() || WINDOWS_RESERVED_CHARS.contains(&c)
+            }
+        }
+    }
+
+    const fn strips_trailing_dots(self) -> bool {
+        !matches!(self, Self::Posix)
+    }
+
+    const fn has_reserved_names(self) -> bool {
+        matches!(self, Self::Windows | Self::Portable)
+    }
+
+    /// In the units that the filesystem limits component length by
+    fn len_of(self, s: &str) -> usize {
+        match self {
+            Self::Posix => s.len(),
+            Self::Windows | Self::Exfat => s.encode_utf16().count(),
+            Self::Portable => s.len().max(s.encode_utf16().count()),
+        }
+    }
+
+    /// Leaves room for `reserve` to be appended afterwards, i.e. a file extension
+    pub fn sanitize(self, component: &str, reserve: &str) -> String {
+        let trim = |s: &mut String| {
+            if self.strips_trailing_dots() {
+                s.truncate(s.trim_end_matches(['.', ' ']).len());
+            }
+        };
+
+        let mut sanitized = component
+            .chars()
+            .map(|c| {
+                if self.is_reserved_char(c) {
+                    REPLACEMENT
+                } else {
+                    c
+                }
+            })
+            .collect::<String>();
+
+        trim(&mut sanitized);
+
+        let max_len = MAX_COMPONENT_LEN.saturating_sub(self.len_of(reserve));
+        while self.len_of(&sanitized) > max_len {
+            sanitized.pop();
+        }
+
+        trim(&mut sanitized);
+
+        if self.has_reserved_names() {
+            let stem = sanitized.split('.').next().unwrap_or_default();
+
+            if WINDOWS_RESERVED_NAMES
+                .iter()
+                .any(|name| name.eq_ignore_ascii_case(stem.trim_end()))
+            {
+                sanitized.insert(stem.len(), REPLACEMENT);
+            }
+        }
+
+        if sanitized.is_empty() {
+            sanitized.push(REPLACEMENT);
+        }
+
+        sanitized
+    }
+}
+
+#[cfg(test)]
+mod test_sanitize {
+    use super::Profile;
+
+    #[test]
+    fn posix_only_replaces_separators() {
+        assert_eq!(
+            Profile::Posix.sanitize("EOS R5 / C: con.", ".dng"),
+            "EOS R5 _ C: con."
+        );
+    }
+
+    #[test]
+    fn windows_replaces_reserved_chars_and_names() {
+        assert_eq!(
+            Profile::Windows.sanitize("EOS R5 / C: <1/4>?", ".dng"),
+            "EOS R5 _ C_ _1_4__"
+        );
+        assert_eq!(Profile::Windows.sanitize("con", ".dng"), "con_");
+        assert_eq!(Profile::Windows.sanitize("LPT1.tar", ".dng"), "LPT1_.tar");
+        assert_eq!(Profile::Windows.sanitize("CONSOLE", ".dng"), "CONSOLE");
+    }
+
+    #[test]
+    fn exfat_allows_device_names() {
+        assert_eq!(Profile::Exfat.sanitize("NUL", ".dng"), "NUL");
+        assert_eq!(Profile::Exfat.sanitize("a|b", ".dng"), "a_b");
+    }
+
+    #[test]
+    fn strips_trailing_dots_and_spaces() {
+        assert_eq!(Profile::Exfat.sanitize("Vol. 1 . ", ""), "Vol. 1");
+        assert_eq!(Profile::Portable.sanitize("...", ""), "_");
+        assert_eq!(Profile::Portable.sanitize("...", ".dng"), "_");
+        assert_eq!(Profile::Posix.sanitize("", ".dng"), "_");
+        assert_eq!(Profile::Posix.sanitize("...", ""), "...");
+    }
+
+    #[test]
+    fn limits_component_length() {
+        let long = "é".repeat(200);
+
+        // 400 bytes, but only 200 UTF-16 code units
+        assert_eq!(Profile::Windows.sanitize(&long, ".dng"), long);
+        assert_eq!(
+            Profile::Posix.sanitize(&long, ".dng").len(),
+            (255 - ".dng".len()) / 2 * 2
+        );
+        assert!(Profile::Portable.sanitize(&long, ".dng").len() <= 255 - ".dng".len());
+    }
+}
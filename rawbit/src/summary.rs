@@ -0,0 +1,188 @@
+// Copyright (c) Carter J. Canedy <cartercanedy42@gmail.com>
+// rawbit is free software, distributable under the terms of the MIT license
+// See https://raw.githubusercontent.com/cartercanedy/rawbit/refs/heads/master/LICENSE.txt
+
+use std::{
+    collections::BTreeMap,
+    fmt::{self, Display},
+};
+
+use crate::{job::Error, plan::Conflict};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Failure {
+    Decode,
+    Io,
+    Verify,
+    Exists,
+    Missing,
+    Other,
+}
+
+impl Failure {
+    pub const fn of(err: &Error) -> Self {
+        match err {
+            Error::ImgOp(..) => Self::Decode,
+            Error::Io(..) => Self::Io,
+            Error::VerifyFailed(_) => Self::Verify,
+            Error::AlreadyExists(_) => Self::Exists,
+            Error::NotFound(_) => Self::Missing,
+            Error::Other(..) => Self::Other,
+        }
+    }
+
+    pub const fn label(self) -> &'static str {
+        match self {
+            Self::Decode => "decode",
+            Self::Io => "io",
+            Self::Verify => "verify",
+            Self::Exists => "exists",
+            Self::Missing => "missing",
+            Self::Other => "other",
+        }
+    }
+}
+
+/// Unsupported and unreadable files are counted once, everything else once per image
+#[derive(Debug, Default)]
+pub struct Summary {
+    pub written: usize,
+    /// includes `exists`
+    pub skipped: usize,
+    /// outputs that were already on disk
+    pub exists: usize,
+    pub unsupported: usize,
+    /// images that weren't attempted because `--fail-fast` stopped the run
+    pub aborted: usize,
+    failed: BTreeMap<Failure, usize>,
+}
+
+impl Summary {
+    pub fn fail(&mut self, err: &Error) {
+        *self.failed.entry(Failure::of(err)).or_default() += 1;
+    }
+
+    pub fn conflict(&mut self, conflict: &Conflict) {
+        match conflict {
+            Conflict::Skipped(..) => self.skipped += 1,
+            Conflict::Exists(..) => {
+                self.skipped += 1;
+                self.exists += 1;
+            }
+            Conflict::Failed(_, err) => self.fail(err),
+        }
+    }
+
+    pub fn finished<T>(&mut self, result: &Result<T, Error>) {
+        match result {
+            Ok(_) => self.written += 1,
+            Err(err) => self.fail(err),
+        }
+    }
+
+    pub fn failures(&self) -> usize {
+        self.failed.values().sum()
+    }
+
+    /// Leaves out kinds that didn't occur
+    pub fn failed(&self) -> impl Iterator<Item = (Failure, usize)> + '_ {
+        self.failed.iter().map(|(&failure, &n)| (failure, n))
+    }
+}
+
+impl Display for Summary {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "summary:")?;
+        writeln!(f, "  {:<16}{}", "written", self.written)?;
+        writeln!(f, "  {:<16}{}", "skipped", self.skipped)?;
+
+        if self.exists > 0 {
+            writeln!(f, "    {:<14}{}", "exists", self.exists)?;
+        }
+
+        writeln!(f, "  {:<16}{}", "unsupported", self.unsupported)?;
+        writeln!(f, "  {:<16}{}", "failed", self.failures())?;
+
+        for (failure, n) in self.failed() {
+            writeln!(f, "    {:<14}{n}", failure.label())?;
+        }
+
+        if self.aborted > 0 {
+            writeln!(f, "  {:<16}{}", "not attempted", self.aborted)?;
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test_summary {
+    use rawler::exif::Exif;
+
+    use super::Summary;
+    use crate::{
+        fixtures,
+        job::Error,
+        plan::{Conflict, Planned},
+    };
+
+    #[test]
+    fn tallies_failures_by_kind() {
+        let mut summary = Summary::default();
+
+        summary.finished(&Ok(()));
+        summary.finished::<()>(&Err(Error::VerifyFailed(String::new())));
+        summary.finished::<()>(&Err(Error::AlreadyExists(String::new())));
+        summary.fail(&Error::VerifyFailed(String::new()));
+
+        assert_eq!(summary.written, 1);
+        assert_eq!(summary.failures(), 3);
+        assert_eq!(
+            summary
+                .failed()
+                .map(|(failure, n)| (failure.label(), n))
+                .collect::<Vec<_>>(),
+            [("verify", 2), ("exists", 1)]
+        );
+    }
+
+    #[test]
+    fn existing_outputs_are_skipped_not_failed() {
+        let mut summary = Summary::default();
+        let planned = || {
+            let md = fixtures::metadata("Fujifilm", "X-T5", Exif::default());
+
+            Planned {
+                source: fixtures::source("a.RAF", md),
+                output_path: "a.dng".into(),
+                overwrite: false,
+                backup_paths: vec![],
+            }
+        };
+
+        summary.conflict(&Conflict::Exists(planned(), String::new()));
+        summary.conflict(&Conflict::Skipped(planned(), String::new()));
+
+        assert_eq!((summary.skipped, summary.exists), (2, 1));
+        assert_eq!(summary.failures(), 0);
+        assert!(summary
+            .to_string()
+            .contains("  skipped         2\n    exists        1\n"));
+    }
+
+    #[test]
+    fn table_leaves_out_empty_rows() {
+        let mut summary = Summary {
+            written: 3,
+            ..Summary::default()
+        };
+        summary.fail(&Error::NotFound(String::new()));
+
+        let table = summary.to_string();
+
+        assert!(table.contains("  written         3\n"));
+        assert!(table.contains("  failed          1\n    missing       1\n"));
+        assert!(!table.contains("verify"));
+        assert!(!table.contains("not attempted"));
+    }
+}
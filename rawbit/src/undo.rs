@@ -0,0 +1,317 @@
+// Copyright (c) Carter J. Canedy <cartercanedy42@gmail.com>
+// rawbit is free software, distributable under the terms of the MIT license
+// See https://raw.githubusercontent.com/cartercanedy/rawbit/refs/heads/master/LICENSE.txt
+
+use std::{
+    collections::HashSet,
+    fs::{remove_dir, remove_file},
+    path::{Path, PathBuf},
+};
+
+use crate::{
+    common::{map_err, sha256_file, sync_dir},
+    job::Error,
+    journal::{Action, Record},
+    output::{copy_file, move_file, parent_dir},
+};
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum Undone {
+    Reverted,
+    /// a source that was deleted by `--move` was copied back from its destination
+    Restored,
+    Skipped(String),
+}
+
+/// Files are only touched if their hash still matches the journal. Sources deleted by `--move`
+/// that were converted, and outputs that replaced an existing file, can't be brought back, so
+/// their outputs are kept.
+pub fn undo<'a>(records: &'a [Record], root: &Path) -> Vec<(&'a Record, Result<Undone, Error>)> {
+    let mut undo = Undo {
+        root,
+        unrestored: HashSet::new(),
+    };
+
+    records
+        .iter()
+        .rev()
+        .map(|record| (record, undo.record(record)))
+        .collect()
+}
+
+struct Undo<'a> {
+    root: &'a Path,
+    /// sources that were deleted by `--move` and couldn't be restored
+    unrestored: HashSet<PathBuf>,
+}
+
+impl Undo<'_> {
+    fn record(&mut self, record: &Record) -> Result<Undone, Error> {
+        let Record {
+            source,
+            destination,
+            hash,
+            ..
+        } = record;
+
+        match record.action {
+            Action::Rename => {
+                if !exists(destination) && matches(source, hash) {
+                    return Ok(Undone::Skipped("already renamed back".into()));
+                }
+
+                check(destination, hash)?;
+                move_file(destination, source)?;
+                self.prune(parent_dir(destination));
+
+                Ok(Undone::Reverted)
+            }
+
+            Action::Convert | Action::Copy => {
+                if self.unrestored.contains(source) {
+                    return Ok(Undone::Skipped(
+                        "kept, its source couldn't be restored".into(),
+                    ));
+                } else if !exists(destination) {
+                    return Ok(Undone::Skipped("already removed".into()));
+                } else if record.replaced {
+                    return Err(Error::NotFound(format!(
+                        "{} replaced an existing file, which can't be restored; keeping it",
+                        destination.display()
+                    )));
+                }
+
+                check(destination, hash)?;
+                remove(destination)?;
+                self.prune(parent_dir(destination));
+
+                Ok(Undone::Reverted)
+            }
+
+            Action::DeleteSource => {
+                let restored = restore(record);
+
+                if restored.is_err() {
+                    self.unrestored.insert(source.clone());
+                }
+
+                restored
+            }
+        }
+    }
+
+    /// Stops at `root`
+    fn prune(&self, dir: &Path) {
+        let mut dir = Some(dir);
+
+        while let Some(empty) = dir.filter(|dir| dir.starts_with(self.root) && *dir != self.root) {
+            if remove_dir(empty).is_err() {
+                break;
+            }
+
+            let _ = sync_dir(parent_dir(empty));
+            dir = empty.parent();
+        }
+    }
+}
+
+fn restore(record: &Record) -> Result<Undone, Error> {
+    let Record {
+        source,
+        source_hash,
+        destination,
+        hash,
+        ..
+    } = record;
+
+    if source_hash.as_ref() != Some(hash) {
+        return Err(Error::NotFound(format!(
+            "{} was converted, so it can't be restored; keeping its outputs",
+            source.display()
+        )));
+    }
+
+    if exists(source) {
+        check(source, hash)?;
+        return Ok(Undone::Skipped("already restored".into()));
+    }
+
+    check(destination, hash)?;
+    copy_file(destination, source)?;
+
+    Ok(Undone::Restored)
+}
+
+fn exists(path: &Path) -> bool {
+    path.symlink_metadata().is_ok()
+}
+
+fn matches(path: &Path, hash: &str) -> bool {
+    sha256_file(path).is_ok_and(|actual| actual == hash)
+}
+
+fn check(path: &Path, hash: &str) -> Result<(), Error> {
+    let actual = map_err!(
+        sha256_file(path),
+        Error::Io,
+        format!("couldn't hash {}", path.display())
+    )?;
+
+    if actual == hash {
+        Ok(())
+    } else {
+        Err(Error::VerifyFailed(format!(
+            "{} was modified since, keeping it",
+            path.display()
+        )))
+    }
+}
+
+fn remove(path: &Path) -> Result<(), Error> {
+    map_err!(
+        remove_file(path),
+        Error::Io,
+        format!("couldn't remove {}", path.display())
+    )?;
+
+    let dir = parent_dir(path);
+    map_err!(
+        sync_dir(dir),
+        Error::Io,
+        format!("couldn't sync dir to disk: {}", dir.display())
+    )
+}
+
+#[cfg(test)]
+mod test_undo {
+    use std::{
+        fs::{create_dir_all, read, write},
+        path::Path,
+    };
+
+    use tempfile::tempdir;
+
+    use super::{undo, Undone};
+    use crate::{
+        common::sha256_file,
+        job::Error,
+        journal::{Action, Record},
+    };
+
+    fn record(action: Action, source: &Path, destination: &Path) -> Record {
+        let hash = sha256_file(destination).unwrap();
+
+        Record {
+            action,
+            source: source.into(),
+            source_hash: (action != Action::Rename).then(|| hash.clone()),
+            destination: destination.into(),
+            hash,
+            replaced: false,
+        }
+    }
+
+    #[test]
+    fn removes_outputs_and_emptied_dirs() {
+        let dir = tempdir().unwrap();
+        let output_path = dir.path().join("2024/06/a.dng");
+        create_dir_all(output_path.parent().unwrap()).unwrap();
+        write(&output_path, b"dng").unwrap();
+
+        let records = [record(
+            Action::Convert,
+            Path::new("/raw/a.RAF"),
+            &output_path,
+        )];
+        let undone = undo(&records, dir.path());
+
+        assert!(matches!(undone[0].1, Ok(Undone::Reverted)));
+        assert!(!dir.path().join("2024").exists());
+        assert!(dir.path().exists());
+
+        let undone = undo(&records, dir.path());
+        assert!(matches!(undone[0].1, Ok(Undone::Skipped(_))));
+    }
+
+    #[test]
+    fn keeps_modified_files() {
+        let dir = tempdir().unwrap();
+        let output_path = dir.path().join("a.dng");
+        write(&output_path, b"dng").unwrap();
+
+        let records = [record(
+            Action::Convert,
+            Path::new("/raw/a.RAF"),
+            &output_path,
+        )];
+        write(&output_path, b"edited").unwrap();
+
+        assert!(matches!(
+            undo(&records, dir.path())[0].1,
+            Err(Error::VerifyFailed(_))
+        ));
+        assert_eq!(read(&output_path).unwrap(), b"edited");
+    }
+
+    #[test]
+    fn renames_files_back() {
+        let dir = tempdir().unwrap();
+        let (source, destination) = (dir.path().join("raw/a.dng"), dir.path().join("b.dng"));
+        write(&destination, b"dng").unwrap();
+
+        let records = [record(Action::Rename, &source, &destination)];
+
+        assert!(matches!(
+            undo(&records, dir.path())[0].1,
+            Ok(Undone::Reverted)
+        ));
+        assert_eq!(read(&source).unwrap(), b"dng");
+        assert!(!destination.exists());
+    }
+
+    #[test]
+    fn restores_moved_copies_but_not_conversions() {
+        let dir = tempdir().unwrap();
+        let copied = (dir.path().join("raw/a.RAF"), dir.path().join("out/a.RAF"));
+        let converted = (dir.path().join("raw/b.RAF"), dir.path().join("out/b.dng"));
+        create_dir_all(dir.path().join("out")).unwrap();
+        write(&copied.1, b"raw").unwrap();
+        write(&converted.1, b"dng").unwrap();
+
+        let mut deleted = record(Action::DeleteSource, &converted.0, &converted.1);
+        deleted.source_hash = Some("raw".into());
+
+        let records = [
+            record(Action::Copy, &copied.0, &copied.1),
+            record(Action::Convert, &converted.0, &converted.1),
+            record(Action::DeleteSource, &copied.0, &copied.1),
+            deleted,
+        ];
+        let undone = undo(&records, dir.path());
+
+        assert!(matches!(undone[0].1, Err(Error::NotFound(_))));
+        assert_eq!(undone[1].1.as_ref().unwrap(), &Undone::Restored);
+        assert!(matches!(undone[2].1, Ok(Undone::Skipped(_))));
+        assert_eq!(undone[3].1.as_ref().unwrap(), &Undone::Reverted);
+
+        assert_eq!(read(&copied.0).unwrap(), b"raw");
+        assert!(!copied.1.exists());
+        assert!(converted.1.exists());
+    }
+
+    #[test]
+    fn keeps_outputs_that_replaced_a_file() {
+        let dir = tempdir().unwrap();
+        let output_path = dir.path().join("a.dng");
+        write(&output_path, b"dng").unwrap();
+
+        let mut replaced = record(Action::Convert, Path::new("/raw/a.RAF"), &output_path);
+        replaced.replaced = true;
+
+        assert!(matches!(
+            undo(&[replaced], dir.path())[0].1,
+            Err(Error::NotFound(_))
+        ));
+        assert!(output_path.exists());
+    }
+}
@@ -0,0 +1,273 @@
+// Copyright (c) Carter J. Canedy <cartercanedy42@gmail.com>
+// rawbit is free software, distributable under the terms of the MIT license
+// See https://raw.githubusercontent.com/cartercanedy/rawbit/refs/heads/master/LICENSE.txt
+
+use std::{
+    fs::File,
+    io::{BufReader, Seek},
+    path::Path,
+};
+
+use clap::ValueEnum;
+use rawler::{
+    decoders::RawDecodeParams,
+    dng::{convert::ConvertParams, CropMode},
+    get_decoder,
+    rawimage::{RawImage, RawImageData, RawPhotometricInterpretation},
+    RawFile,
+};
+
+use crate::{common::map_err, job::Error};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, ValueEnum)]
+pub enum Verify {
+    /// decode the DNG and compare its dimensions and CFA pattern to the source
+    Decode,
+    /// also compare every raw pixel value to the source
+    Pixels,
+}
+
+pub fn verify(
+    input_path: &Path,
+    dng: &mut File,
+    params: &ConvertParams,
+    level: Verify,
+) -> Result<(), Error> {
+    let input = map_err!(
+        File::open(input_path),
+        Error::Io,
+        "Couldn't open input RAW file"
+    )?;
+
+    let source = map_err!(
+        decode(input_path, input, params.index),
+        Error::ImgOp,
+        "couldn't decode source image for verification",
+    )?;
+
+    map_err!(dng.rewind(), Error::Io, "couldn't read back converted DNG")?;
+    let dng = map_err!(
+        dng.try_clone(),
+        Error::Io,
+        "couldn't read back converted DNG"
+    )?;
+
+    let converted = map_err!(
+        decode(input_path, dng, 0),
+        Error::ImgOp,
+        "converted DNG can't be decoded",
+    )?;
+
+    compare(&source, &converted, params, level).map_err(|mismatch| {
+        Error::VerifyFailed(format!(
+            "converted DNG doesn't match the source: {mismatch}"
+        ))
+    })
+}
+
+fn decode(path: &Path, file: File, index: usize) -> rawler::Result<RawImage> {
+    let mut raw_file = RawFile::new(path, BufReader::new(file));
+    let decoder = get_decoder(&mut raw_file)?;
+
+    // without `dummy`, so that the image data is decompressed too
+    decoder.raw_image(&mut raw_file, RawDecodeParams { image_index: index }, false)
+}
+
+fn compare(
+    source: &RawImage,
+    converted: &RawImage,
+    params: &ConvertParams,
+    level: Verify,
+) -> Result<(), String> {
+    let dimensions = |image: &RawImage| (image.width, image.height, image.cpp);
+
+    if dimensions(source) != dimensions(converted) {
+        return Err(format!(
+            "dimensions are {}x{}x{}, expected {}x{}x{}",
+            converted.width,
+            converted.height,
+            converted.cpp,
+            source.width,
+            source.height,
+            source.cpp
+        ));
+    }
+
+    // the pattern is stored relative to the top left corner of the area that was kept
+    let (x, y) = match params.crop {
+        CropMode::None => (0, 0),
+        CropMode::Best | CropMode::ActiveArea => source
+            .active_area
+            .map_or((0, 0), |area| (area.p.x, area.p.y)),
+    };
+
+    let expected_cfa = cfa_pattern(&source.photometric).map(|cfa| cfa.shift(x, y).to_string());
+    let converted_cfa = cfa_pattern(&converted.photometric).map(ToString::to_string);
+
+    if expected_cfa != converted_cfa {
+        return Err(format!(
+            "CFA pattern is {}, expected {}",
+            converted_cfa.as_deref().unwrap_or("none"),
+            expected_cfa.as_deref().unwrap_or("none")
+        ));
+    }
+
+    if level == Verify::Pixels {
+        let equal = match (&source.data, &converted.data) {
+            (RawImageData::Integer(a), RawImageData::Integer(b)) => a == b,
+            (RawImageData::Float(a), RawImageData::Float(b)) => a == b,
+            _ => false,
+        };
+
+        if !equal {
+            return Err("pixel data differs".into());
+        }
+    }
+
+    Ok(())
+}
+
+const fn cfa_pattern(photometric: &RawPhotometricInterpretation) -> Option<&rawler::CFA> {
+    match photometric {
+        RawPhotometricInterpretation::Cfa(config) => Some(&config.cfa),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod test_verify {
+    use std::{
+        fs::File,
+        io::{BufReader, BufWriter, Read as _, Seek as _, SeekFrom, Write as _},
+        path::Path,
+    };
+
+    use rawler::dng::{
+        convert::{convert_raw_stream, ConvertParams},
+        CropMode,
+    };
+    use tempfile::{tempdir, tempfile};
+
+    use super::{verify, Verify};
+    use crate::{fixtures::write_dng, job::Error};
+
+    fn params(crop: CropMode) -> ConvertParams {
+        ConvertParams {
+            crop,
+            embedded: false,
+            preview: false,
+            thumbnail: false,
+            ..Default::default()
+        }
+    }
+
+    fn convert(input_path: &Path, params: &ConvertParams) -> File {
+        let input = BufReader::new(File::open(input_path).unwrap());
+        let mut output = BufWriter::new(tempfile().unwrap());
+
+        convert_raw_stream(input, &mut output, "source.dng", params).unwrap();
+        output.into_inner().unwrap()
+    }
+
+    #[test]
+    fn accepts_faithful_conversions() {
+        let dir = tempdir().unwrap();
+        let input_path = dir.path().join("source.dng");
+        write_dng(&mut File::create(&input_path).unwrap(), "RGGB", |i| {
+            i % 4096
+        });
+
+        for crop in [CropMode::Best, CropMode::ActiveArea, CropMode::None] {
+            let params = params(crop);
+            let mut converted = convert(&input_path, &params);
+
+            verify(&input_path, &mut converted, &params, Verify::Pixels).unwrap();
+        }
+    }
+
+    #[test]
+    fn rejects_different_pixels() {
+        let dir = tempdir().unwrap();
+        let input_path = dir.path().join("source.dng");
+        write_dng(&mut File::create(&input_path).unwrap(), "RGGB", |i| {
+            i % 4096
+        });
+
+        let mut converted = tempfile().unwrap();
+        write_dng(&mut converted, "RGGB", |i| (i % 4096) ^ 1);
+
+        let params = params(CropMode::None);
+        verify(&input_path, &mut converted, &params, Verify::Decode).unwrap();
+
+        assert!(matches!(
+            verify(&input_path, &mut converted, &params, Verify::Pixels),
+            Err(Error::VerifyFailed(_))
+        ));
+    }
+
+    #[test]
+    fn rejects_different_cfa_pattern() {
+        let dir = tempdir().unwrap();
+        let input_path = dir.path().join("source.dng");
+        write_dng(&mut File::create(&input_path).unwrap(), "RGGB", |_| 0);
+
+        // converting shifts the pattern by the active area's origin once more
+        let mut converted = tempfile().unwrap();
+        write_dng(&mut converted, "RGGB", |_| 0);
+
+        assert!(matches!(
+            verify(
+                &input_path,
+                &mut converted,
+                &params(CropMode::ActiveArea),
+                Verify::Decode
+            ),
+            Err(Error::VerifyFailed(_))
+        ));
+    }
+
+    #[test]
+    fn rejects_undecodable_output() {
+        let dir = tempdir().unwrap();
+        let input_path = dir.path().join("source.dng");
+        write_dng(&mut File::create(&input_path).unwrap(), "RGGB", |_| 0);
+
+        let mut converted = tempfile().unwrap();
+        converted.write_all(b"II*\0 not a DNG").unwrap();
+
+        assert!(matches!(
+            verify(
+                &input_path,
+                &mut converted,
+                &params(CropMode::Best),
+                Verify::Decode
+            ),
+            Err(Error::ImgOp(..))
+        ));
+    }
+
+    #[test]
+    fn rejects_corrupt_image_data() {
+        let dir = tempdir().unwrap();
+        let input_path = dir.path().join("source.dng");
+        write_dng(&mut File::create(&input_path).unwrap(), "RGGB", |i| {
+            i % 4096
+        });
+
+        let params = params(CropMode::None);
+        let mut converted = convert(&input_path, &params);
+
+        // wipes the start of the first lossless JPEG tile, as if its data was never written
+        let mut bytes = vec![];
+        converted.rewind().unwrap();
+        converted.read_to_end(&mut bytes).unwrap();
+        let soi = bytes.windows(2).position(|w| w == [0xff, 0xd8]).unwrap();
+        converted.seek(SeekFrom::Start(soi as u64)).unwrap();
+        converted.write_all(&[0; 64]).unwrap();
+
+        assert!(matches!(
+            verify(&input_path, &mut converted, &params, Verify::Decode),
+            Err(Error::ImgOp(..))
+        ));
+    }
+}